@@ -0,0 +1,789 @@
+//! Two-pass assembler for the mnemonics produced by [`crate::disassemble`].
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! SPEED EQU 2
+//! start:  LD V0, 0
+//!         LD I, sprite
+//! loop:   DRW V0, V1, 5
+//!         ADD V0, SPEED
+//!         JP loop
+//! sprite: DB 0xF0, 0x90, 0x90, 0x90, 0xF0
+//!         INCLUDE "more.asm"
+//! ```
+//!
+//! The first pass lays out every line and records label addresses, the second
+//! evaluates operands, so labels can be used before they are defined.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const BASE_ADDR: u16 = 0x200;
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Output of a successful assembly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    /// Labels and constants with their values, ordered by value.
+    pub symbols: Vec<(String, u16)>,
+}
+
+impl Assembly {
+    /// Renders the symbol table as `NAME 0x0200` lines.
+    pub fn symbol_map(&self) -> String {
+        self.symbols
+            .iter()
+            .map(|(name, value)| format!("{} {:#06X}\n", name, value))
+            .collect()
+    }
+}
+
+/// A problem at a particular place in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+/// Assembles `source`; `INCLUDE` paths are resolved against the working directory.
+pub fn assemble(source: &str) -> Result<Assembly, Vec<AsmError>> {
+    let mut asm = Assembler::new(|path: &Path| fs::read_to_string(path).map_err(|e| e.to_string()));
+    asm.source("<input>", Path::new("."), source, 0);
+    asm.finish()
+}
+
+/// Assembles the file at `path`; `INCLUDE` paths are resolved against its directory.
+pub fn assemble_file(path: &Path) -> Result<Assembly, Vec<AsmError>> {
+    let mut asm = Assembler::new(|path: &Path| fs::read_to_string(path).map_err(|e| e.to_string()));
+    asm.include(path, None, 0);
+    asm.finish()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Num(i64),
+    Str(String),
+    Punct(char),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Loc {
+    file: String,
+    line: usize,
+    column: usize,
+}
+
+impl Loc {
+    fn at(&self, column: usize) -> Loc {
+        Loc {
+            column,
+            ..self.clone()
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Instr(String, Vec<Vec<Token>>),
+    Db(Vec<Vec<Token>>),
+    Dw(Vec<Vec<Token>>),
+}
+
+#[derive(Debug, Clone)]
+struct Statement {
+    loc: Loc,
+    addr: u16,
+    item: Item,
+}
+
+struct Assembler<F> {
+    read: F,
+    addr: u32,
+    statements: Vec<Statement>,
+    labels: HashMap<String, (u16, Loc)>,
+    constants: HashMap<String, (Vec<Token>, Loc)>,
+    errors: Vec<AsmError>,
+}
+
+enum Operand {
+    V(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(i64),
+    Value(i64),
+}
+
+impl<F: Fn(&Path) -> Result<String, String>> Assembler<F> {
+    fn new(read: F) -> Self {
+        Self {
+            read,
+            addr: BASE_ADDR as u32,
+            statements: Vec::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn include(&mut self, path: &Path, from: Option<&Loc>, depth: usize) {
+        let name = path.display().to_string();
+        if depth > MAX_INCLUDE_DEPTH {
+            let loc = from.cloned().unwrap_or(Loc {
+                file: name,
+                line: 0,
+                column: 0,
+            });
+            self.errors.push(loc.error("includes nested too deeply"));
+            return;
+        }
+        match (self.read)(path) {
+            Ok(source) => {
+                let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+                self.source(&name, &dir, &source, depth);
+            }
+            Err(err) => {
+                let loc = from.cloned().unwrap_or(Loc {
+                    file: name.clone(),
+                    line: 0,
+                    column: 0,
+                });
+                self.errors
+                    .push(loc.error(format!("cannot read {}: {}", name, err)));
+            }
+        }
+    }
+
+    // pass one: tokenize, lay out addresses and collect symbols
+    fn source(&mut self, name: &str, dir: &Path, source: &str, depth: usize) {
+        for (idx, text) in source.lines().enumerate() {
+            let loc = Loc {
+                file: name.to_string(),
+                line: idx + 1,
+                column: 1,
+            };
+            let tokens = match tokenize(text) {
+                Ok(tokens) => tokens,
+                Err(column) => {
+                    self.errors
+                        .push(loc.at(column).error("unexpected character"));
+                    continue;
+                }
+            };
+            if let Err(err) = self.line(&loc, dir, tokens, depth) {
+                self.errors.push(err);
+            }
+        }
+    }
+
+    fn line(
+        &mut self,
+        loc: &Loc,
+        dir: &Path,
+        mut tokens: Vec<Token>,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        // label:
+        if let (Some(Tok::Ident(name)), Some(Tok::Punct(':'))) = (
+            tokens.first().map(|t| &t.tok),
+            tokens.get(1).map(|t| &t.tok),
+        ) {
+            let name = name.clone();
+            let label_loc = loc.at(tokens[0].column);
+            self.define_label(name, label_loc)?;
+            tokens.drain(..2);
+        }
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        // NAME EQU expr
+        if let (Some(Tok::Ident(name)), Some(Tok::Ident(equ))) = (
+            tokens.first().map(|t| &t.tok),
+            tokens.get(1).map(|t| &t.tok),
+        ) {
+            if equ.eq_ignore_ascii_case("EQU") {
+                let name = name.clone();
+                let const_loc = loc.at(tokens[0].column);
+                if tokens.len() < 3 {
+                    return Err(loc.at(tokens[1].column).error("EQU needs a value"));
+                }
+                if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+                    return Err(const_loc.error(format!("{} is already defined", name)));
+                }
+                self.constants
+                    .insert(name, (tokens[2..].to_vec(), const_loc));
+                return Ok(());
+            }
+        }
+
+        let (mnemonic, column) = match &tokens[0].tok {
+            Tok::Ident(name) => (name.to_ascii_uppercase(), tokens[0].column),
+            _ => return Err(loc.at(tokens[0].column).error("expected an instruction")),
+        };
+        let loc = loc.at(column);
+        let operands = split_operands(&tokens[1..]);
+
+        let (item, size) = match mnemonic.as_str() {
+            "INCLUDE" => {
+                let path = match operands.as_slice() {
+                    [op] if op.len() == 1 => match &op[0].tok {
+                        Tok::Str(path) => path.clone(),
+                        _ => return Err(loc.error("INCLUDE expects a quoted path")),
+                    },
+                    _ => return Err(loc.error("INCLUDE expects a quoted path")),
+                };
+                let path: PathBuf = dir.join(path);
+                self.include(&path, Some(&loc), depth + 1);
+                return Ok(());
+            }
+            "DB" => {
+                let mut size = 0;
+                for op in &operands {
+                    size += match op.as_slice() {
+                        [Token {
+                            tok: Tok::Str(s), ..
+                        }] => s.len(),
+                        _ => 1,
+                    };
+                }
+                (Item::Db(operands), size)
+            }
+            "DW" => {
+                let size = operands.len() * 2;
+                (Item::Dw(operands), size)
+            }
+            _ => {
+                let long = operands.iter().any(|op| {
+                    matches!(op.first(), Some(Token { tok: Tok::Ident(name), .. }) if name.eq_ignore_ascii_case("LONG"))
+                });
+                let size = if long { 4 } else { 2 };
+                (Item::Instr(mnemonic, operands), size)
+            }
+        };
+        if self.addr + size as u32 > 0x10000 {
+            return Err(loc.error("program does not fit in memory"));
+        }
+        self.statements.push(Statement {
+            loc,
+            addr: self.addr as u16,
+            item,
+        });
+        self.addr += size as u32;
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: String, loc: Loc) -> Result<(), AsmError> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return Err(loc.error(format!("{} is already defined", name)));
+        }
+        if self.addr > 0xFFFF {
+            return Err(loc.error("label is past the end of memory"));
+        }
+        self.labels.insert(name, (self.addr as u16, loc));
+        Ok(())
+    }
+
+    // pass two: evaluate operands and encode
+    fn finish(mut self) -> Result<Assembly, Vec<AsmError>> {
+        let mut rom = Vec::new();
+        let statements = std::mem::take(&mut self.statements);
+        for stmt in &statements {
+            let offset = (stmt.addr - BASE_ADDR) as usize;
+            if rom.len() < offset {
+                rom.resize(offset, 0);
+            }
+            match self.encode(stmt) {
+                Ok(bytes) => rom.extend(bytes),
+                Err(err) => self.errors.push(err),
+            }
+        }
+
+        let mut symbols: Vec<(String, u16)> = self
+            .labels
+            .iter()
+            .map(|(name, (addr, _))| (name.clone(), *addr))
+            .collect();
+        let names: Vec<String> = self.constants.keys().cloned().collect();
+        for name in names {
+            let loc = self.constants[&name].1.clone();
+            match self.constant(&name, &loc, &mut Vec::new()) {
+                Ok(value) => symbols.push((name, value as u16)),
+                Err(err) => self.errors.push(err),
+            }
+        }
+        if !self.errors.is_empty() {
+            self.errors
+                .sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+            self.errors.dedup();
+            return Err(self.errors);
+        }
+        symbols.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        Ok(Assembly { rom, symbols })
+    }
+
+    fn constant(&self, name: &str, loc: &Loc, seen: &mut Vec<String>) -> Result<i64, AsmError> {
+        if seen.iter().any(|s| s == name) {
+            return Err(loc.error(format!("{} is defined in terms of itself", name)));
+        }
+        seen.push(name.to_string());
+        let (tokens, const_loc) = &self.constants[name];
+        let value = self.expr(tokens, const_loc, seen);
+        seen.pop();
+        value
+    }
+
+    fn expr(&self, tokens: &[Token], loc: &Loc, seen: &mut Vec<String>) -> Result<i64, AsmError> {
+        if tokens.is_empty() {
+            return Err(loc.error("expected a value"));
+        }
+        let mut total = 0i64;
+        let mut sign = 1i64;
+        let mut expect_term = true;
+        for token in tokens {
+            let here = loc.at(token.column);
+            let add = |total: i64, value: i64| {
+                sign.checked_mul(value)
+                    .and_then(|value| total.checked_add(value))
+                    .ok_or_else(|| here.error("expression overflows"))
+            };
+            match (&token.tok, expect_term) {
+                (Tok::Punct('-'), true) => sign = -sign,
+                (Tok::Punct('+'), true) => {}
+                (Tok::Num(n), true) => {
+                    total = add(total, *n)?;
+                    expect_term = false;
+                }
+                (Tok::Ident(name), true) => {
+                    let value = if let Some((addr, _)) = self.labels.get(name) {
+                        *addr as i64
+                    } else if self.constants.contains_key(name) {
+                        self.constant(name, &here, seen)?
+                    } else {
+                        return Err(here.error(format!("undefined symbol {}", name)));
+                    };
+                    total = add(total, value)?;
+                    expect_term = false;
+                }
+                (Tok::Punct('+'), false) => {
+                    sign = 1;
+                    expect_term = true;
+                }
+                (Tok::Punct('-'), false) => {
+                    sign = -1;
+                    expect_term = true;
+                }
+                _ => return Err(here.error("malformed expression")),
+            }
+        }
+        if expect_term {
+            return Err(loc
+                .at(tokens[tokens.len() - 1].column)
+                .error("expression ends early"));
+        }
+        Ok(total)
+    }
+
+    fn operand(&self, tokens: &[Token], loc: &Loc) -> Result<Operand, AsmError> {
+        let here = loc.at(tokens.first().map(|t| t.column).unwrap_or(loc.column));
+        if tokens.is_empty() {
+            return Err(here.error("missing operand"));
+        }
+        if let [Token {
+            tok: Tok::Punct('['),
+            ..
+        }, Token {
+            tok: Tok::Ident(i), ..
+        }, Token {
+            tok: Tok::Punct(']'),
+            ..
+        }] = tokens
+        {
+            if i.eq_ignore_ascii_case("I") {
+                return Ok(Operand::IndirectI);
+            }
+        }
+        if let Tok::Ident(name) = &tokens[0].tok {
+            let upper = name.to_ascii_uppercase();
+            if upper == "LONG" {
+                let value = self.expr(&tokens[1..], &here, &mut Vec::new())?;
+                return Ok(Operand::Long(value));
+            }
+            if tokens.len() == 1
+                && !self.labels.contains_key(name)
+                && !self.constants.contains_key(name)
+            {
+                let reg = match upper.as_str() {
+                    "I" => Some(Operand::I),
+                    "DT" => Some(Operand::Dt),
+                    "ST" => Some(Operand::St),
+                    "K" => Some(Operand::K),
+                    "F" => Some(Operand::F),
+                    "HF" => Some(Operand::Hf),
+                    "B" => Some(Operand::B),
+                    "R" => Some(Operand::R),
+                    _ if upper.len() == 2 && upper.starts_with('V') => {
+                        u8::from_str_radix(&upper[1..], 16).ok().map(Operand::V)
+                    }
+                    _ => None,
+                };
+                if let Some(reg) = reg {
+                    return Ok(reg);
+                }
+            }
+        }
+        Ok(Operand::Value(self.expr(tokens, &here, &mut Vec::new())?))
+    }
+
+    fn encode(&self, stmt: &Statement) -> Result<Vec<u8>, AsmError> {
+        let loc = &stmt.loc;
+        match &stmt.item {
+            Item::Db(values) => {
+                let mut bytes = Vec::new();
+                for value in values {
+                    if let [Token {
+                        tok: Tok::Str(s), ..
+                    }] = value.as_slice()
+                    {
+                        bytes.extend(s.bytes());
+                        continue;
+                    }
+                    let here = loc.at(value.first().map(|t| t.column).unwrap_or(loc.column));
+                    let n = self.expr(value, &here, &mut Vec::new())?;
+                    bytes.push(check(n, -0x80, 0xFF, &here, "byte")? as u8);
+                }
+                Ok(bytes)
+            }
+            Item::Dw(values) => {
+                let mut bytes = Vec::new();
+                for value in values {
+                    let here = loc.at(value.first().map(|t| t.column).unwrap_or(loc.column));
+                    let n = self.expr(value, &here, &mut Vec::new())?;
+                    let word = check(n, -0x8000, 0xFFFF, &here, "word")? as u16;
+                    bytes.extend(word.to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            Item::Instr(mnemonic, operands) => {
+                let ops = operands
+                    .iter()
+                    .map(|op| self.operand(op, loc))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let col = |i: usize| {
+                    loc.at(operands
+                        .get(i)
+                        .and_then(|op| op.first())
+                        .map(|t| t.column)
+                        .unwrap_or(loc.column))
+                };
+                let nnn = |i: usize, n: i64| -> Result<u16, AsmError> {
+                    Ok(check(n, 0, 0xFFF, &col(i), "address")? as u16)
+                };
+                let nn = |i: usize, n: i64| -> Result<u16, AsmError> {
+                    Ok(check(n, -0x80, 0xFF, &col(i), "byte")? as u8 as u16)
+                };
+                let nibble = |i: usize, n: i64| -> Result<u16, AsmError> {
+                    Ok(check(n, 0, 0xF, &col(i), "nibble")? as u16)
+                };
+                let xy = |x: u8, y: u8| (x as u16) << 8 | (y as u16) << 4;
+                use Operand::*;
+                let op: u16 = match (mnemonic.as_str(), ops.as_slice()) {
+                    ("NOP", []) => 0x0000,
+                    ("CLS", []) => 0x00E0,
+                    ("RET", []) => 0x00EE,
+                    ("SCR", []) => 0x00FB,
+                    ("SCL", []) => 0x00FC,
+                    ("EXIT", []) => 0x00FD,
+                    ("LOW", []) => 0x00FE,
+                    ("HIGH", []) => 0x00FF,
+                    ("AUDIO", []) => 0xF002,
+                    ("SCD", [Value(n)]) => 0x00C0 | nibble(0, *n)?,
+                    ("SCU", [Value(n)]) => 0x00D0 | nibble(0, *n)?,
+                    ("SYS", [Value(a)]) => nnn(0, *a)?,
+                    ("JP", [Value(a)]) => 0x1000 | nnn(0, *a)?,
+                    ("JP", [V(0), Value(a)]) => 0xB000 | nnn(1, *a)?,
+                    ("CALL", [Value(a)]) => 0x2000 | nnn(0, *a)?,
+                    ("SE", [V(x), Value(n)]) => 0x3000 | xy(*x, 0) | nn(1, *n)?,
+                    ("SNE", [V(x), Value(n)]) => 0x4000 | xy(*x, 0) | nn(1, *n)?,
+                    ("SE", [V(x), V(y)]) => 0x5000 | xy(*x, *y),
+                    ("SAVE", [V(x), V(y)]) => 0x5002 | xy(*x, *y),
+                    ("LOAD", [V(x), V(y)]) => 0x5003 | xy(*x, *y),
+                    ("LD", [V(x), Value(n)]) => 0x6000 | xy(*x, 0) | nn(1, *n)?,
+                    ("ADD", [V(x), Value(n)]) => 0x7000 | xy(*x, 0) | nn(1, *n)?,
+                    ("LD", [V(x), V(y)]) => 0x8000 | xy(*x, *y),
+                    ("OR", [V(x), V(y)]) => 0x8001 | xy(*x, *y),
+                    ("AND", [V(x), V(y)]) => 0x8002 | xy(*x, *y),
+                    ("XOR", [V(x), V(y)]) => 0x8003 | xy(*x, *y),
+                    ("ADD", [V(x), V(y)]) => 0x8004 | xy(*x, *y),
+                    ("SUB", [V(x), V(y)]) => 0x8005 | xy(*x, *y),
+                    ("SHR", [V(x)]) => 0x8006 | xy(*x, *x),
+                    ("SHR", [V(x), V(y)]) => 0x8006 | xy(*x, *y),
+                    ("SUBN", [V(x), V(y)]) => 0x8007 | xy(*x, *y),
+                    ("SHL", [V(x)]) => 0x800E | xy(*x, *x),
+                    ("SHL", [V(x), V(y)]) => 0x800E | xy(*x, *y),
+                    ("SNE", [V(x), V(y)]) => 0x9000 | xy(*x, *y),
+                    ("LD", [I, Value(a)]) => 0xA000 | nnn(1, *a)?,
+                    ("RND", [V(x), Value(n)]) => 0xC000 | xy(*x, 0) | nn(1, *n)?,
+                    ("DRW", [V(x), V(y), Value(n)]) => 0xD000 | xy(*x, *y) | nibble(2, *n)?,
+                    ("SKP", [V(x)]) => 0xE09E | xy(*x, 0),
+                    ("SKNP", [V(x)]) => 0xE0A1 | xy(*x, 0),
+                    ("PLANE", [Value(n)]) => {
+                        0xF001 | (check(*n, 0, 3, &col(0), "plane")? as u16) << 8
+                    }
+                    ("LD", [V(x), Dt]) => 0xF007 | xy(*x, 0),
+                    ("LD", [V(x), K]) => 0xF00A | xy(*x, 0),
+                    ("LD", [Dt, V(x)]) => 0xF015 | xy(*x, 0),
+                    ("LD", [St, V(x)]) => 0xF018 | xy(*x, 0),
+                    ("ADD", [I, V(x)]) => 0xF01E | xy(*x, 0),
+                    ("LD", [F, V(x)]) => 0xF029 | xy(*x, 0),
+                    ("LD", [Hf, V(x)]) => 0xF030 | xy(*x, 0),
+                    ("LD", [B, V(x)]) => 0xF033 | xy(*x, 0),
+                    ("PITCH", [V(x)]) => 0xF03A | xy(*x, 0),
+                    ("LD", [IndirectI, V(x)]) => 0xF055 | xy(*x, 0),
+                    ("LD", [V(x), IndirectI]) => 0xF065 | xy(*x, 0),
+                    ("LD", [R, V(x)]) => 0xF075 | xy(*x, 0),
+                    ("LD", [V(x), R]) => 0xF085 | xy(*x, 0),
+                    ("LD", [I, Long(a)]) => {
+                        let addr = check(*a, 0, 0xFFFF, &col(1), "address")? as u16;
+                        let mut bytes = vec![0xF0, 0x00];
+                        bytes.extend(addr.to_be_bytes());
+                        return Ok(bytes);
+                    }
+                    (
+                        "NOP" | "CLS" | "RET" | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "AUDIO"
+                        | "SCD" | "SCU" | "SYS" | "JP" | "CALL" | "SE" | "SNE" | "SAVE" | "LOAD"
+                        | "LD" | "ADD" | "OR" | "AND" | "XOR" | "SUB" | "SHR" | "SUBN" | "SHL"
+                        | "RND" | "DRW" | "SKP" | "SKNP" | "PLANE" | "PITCH",
+                        _,
+                    ) => return Err(loc.error(format!("invalid operands for {}", mnemonic))),
+                    _ => return Err(loc.error(format!("unknown instruction {}", mnemonic))),
+                };
+                Ok(op.to_be_bytes().to_vec())
+            }
+        }
+    }
+}
+
+fn check(n: i64, min: i64, max: i64, loc: &Loc, what: &str) -> Result<i64, AsmError> {
+    if n < min || n > max {
+        return Err(loc.error(format!("{} out of range for a {}", n, what)));
+    }
+    Ok(n)
+}
+
+fn split_operands(tokens: &[Token]) -> Vec<Vec<Token>> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+    tokens
+        .split(|t| t.tok == Tok::Punct(','))
+        .map(|op| op.to_vec())
+        .collect()
+}
+
+// returns the 1-based column of the offending character on failure
+fn tokenize(line: &str) -> Result<Vec<Token>, usize> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        let column = pos + 1;
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            pos += 1;
+        } else if c == '"' {
+            let end = chars[pos + 1..]
+                .iter()
+                .position(|c| *c == '"')
+                .ok_or(column)?;
+            let s: String = chars[pos + 1..pos + 1 + end].iter().collect();
+            tokens.push(Token {
+                tok: Tok::Str(s),
+                column,
+            });
+            pos += end + 2;
+        } else if c.is_ascii_digit() {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            let text: String = chars[start..pos].iter().filter(|c| **c != '_').collect();
+            let lower = text.to_ascii_lowercase();
+            let value = if let Some(hex) = lower.strip_prefix("0x") {
+                i64::from_str_radix(hex, 16)
+            } else if let Some(bin) = lower.strip_prefix("0b") {
+                i64::from_str_radix(bin, 2)
+            } else {
+                lower.parse()
+            };
+            tokens.push(Token {
+                tok: Tok::Num(value.map_err(|_| column)?),
+                column,
+            });
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let start = pos;
+            while pos < chars.len()
+                && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_' || chars[pos] == '.')
+            {
+                pos += 1;
+            }
+            tokens.push(Token {
+                tok: Tok::Ident(chars[start..pos].iter().collect()),
+                column,
+            });
+        } else if ",:[]+-".contains(c) {
+            tokens.push(Token {
+                tok: Tok::Punct(c),
+                column,
+            });
+            pos += 1;
+        } else {
+            return Err(column);
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble;
+
+    fn assemble_with(files: &[(&str, &str)]) -> Result<Assembly, Vec<AsmError>> {
+        let files: HashMap<PathBuf, String> = files
+            .iter()
+            .map(|(name, src)| (Path::new(".").join(name), src.to_string()))
+            .collect();
+        let mut asm = Assembler::new(|path: &Path| {
+            files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| "not found".to_string())
+        });
+        asm.include(&Path::new(".").join("main.asm"), None, 0);
+        asm.finish()
+    }
+
+    #[test]
+    fn test_forward_labels_and_data() {
+        let asm = assemble(
+            "start: LD I, sprite ; forward reference\n\
+             \tDRW V0, V1, SIZE\n\
+             \tJP start\n\
+             SIZE EQU end - sprite\n\
+             sprite: DB 0xF0, 0x90\n\
+             \tDW 0x1234\n\
+             end:\n",
+        )
+        .unwrap();
+        assert_eq!(
+            asm.rom,
+            vec![0xA2, 0x06, 0xD0, 0x14, 0x12, 0x00, 0xF0, 0x90, 0x12, 0x34]
+        );
+        assert!(asm.symbols.contains(&("sprite".to_string(), 0x206)));
+        assert!(asm.symbols.contains(&("SIZE".to_string(), 4)));
+        assert!(asm.symbol_map().contains("start 0x0200\n"));
+    }
+
+    #[test]
+    fn test_round_trips_disassembly() {
+        let rom = [
+            0x00, 0xE0, 0x63, 0x1F, 0x83, 0x46, 0xD0, 0x15, 0xF3, 0x65, 0xF0, 0x00, 0x12, 0x34,
+            0x52, 0x43, 0xFF, 0xFF, 0x00, 0xFF,
+        ];
+        let source: String = disassemble(&rom, 0x200)
+            .iter()
+            .map(|line| {
+                line.to_string()[6..]
+                    .trim()
+                    .split("  ")
+                    .last()
+                    .unwrap()
+                    .to_string()
+                    + "\n"
+            })
+            .collect();
+        assert_eq!(assemble(&source).unwrap().rom, rom);
+    }
+
+    #[test]
+    fn test_errors_have_positions() {
+        let errors = assemble("  LD V0, 0x100\n  JP nowhere\n  FOO V1\n").unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!((errors[0].line, errors[0].column), (1, 10));
+        assert_eq!((errors[1].line, errors[1].column), (2, 6));
+        assert_eq!(errors[1].message, "undefined symbol nowhere");
+        assert_eq!((errors[2].line, errors[2].column), (3, 3));
+    }
+
+    #[test]
+    fn test_include() {
+        let asm = assemble_with(&[
+            ("main.asm", "CALL draw\nINCLUDE \"lib.asm\"\n"),
+            ("lib.asm", "draw: CLS\nRET\n"),
+        ])
+        .unwrap();
+        assert_eq!(asm.rom, vec![0x22, 0x02, 0x00, 0xE0, 0x00, 0xEE]);
+
+        let errors = assemble_with(&[("main.asm", "INCLUDE \"missing.asm\"\n")]).unwrap_err();
+        assert_eq!((errors[0].line, errors[0].column), (1, 1));
+    }
+
+    #[test]
+    fn test_duplicate_and_recursive_symbols() {
+        let errors = assemble("a: CLS\na: CLS\nB EQU C\nC EQU B\n").unwrap_err();
+        assert!(errors.iter().any(|e| e.message == "a is already defined"));
+        assert!(errors
+            .iter()
+            .any(|e| e.message.contains("in terms of itself")));
+    }
+
+    #[test]
+    fn test_expression_overflow() {
+        let source = "BIG EQU 0x7FFFFFFFFFFFFFFF
+LD V0, BIG + BIG
+";
+        let errors = assemble(source).unwrap_err();
+        assert_eq!(errors[0].message, "expression overflows");
+        assert_eq!((errors[0].line, errors[0].column), (2, 14));
+    }
+}
@@ -0,0 +1,117 @@
+pub const PATTERN_SIZE: usize = 16;
+pub const DEFAULT_PITCH: u8 = 64;
+const BEEP_FREQUENCY: f32 = 440.0;
+const PATTERN_BITS: f32 = (PATTERN_SIZE * 8) as f32;
+
+/// What the sound hardware should be doing right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioState {
+    pub beeping: bool,
+    /// XO-CHIP 1-bit sample loop, `None` for the plain buzzer.
+    pub pattern: Option<[u8; PATTERN_SIZE]>,
+    /// XO-CHIP playback rate, 64 plays the pattern at 4000 bits per second.
+    pub pitch: u8,
+}
+
+impl Default for AudioState {
+    fn default() -> Self {
+        Self {
+            beeping: false,
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+        }
+    }
+}
+
+impl AudioState {
+    /// Pattern bits played per second for the current pitch.
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+}
+
+/// Turns an `AudioState` into signed PCM samples.
+#[derive(Debug, Clone)]
+pub struct AudioGenerator {
+    sample_rate: u32,
+    volume: f32,
+    phase: f32,
+}
+
+impl AudioGenerator {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            volume: 0.25,
+            phase: 0.0,
+        }
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+    }
+
+    pub fn fill(&mut self, state: &AudioState, out: &mut [f32]) {
+        if !state.beeping {
+            self.phase = 0.0;
+            out.fill(0.0);
+            return;
+        }
+        let rate = self.sample_rate as f32;
+        for sample in out.iter_mut() {
+            let high = match &state.pattern {
+                Some(pattern) => {
+                    let bit = self.phase as usize;
+                    self.phase = (self.phase + state.playback_rate() / rate) % PATTERN_BITS;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                None => {
+                    let high = self.phase < 0.5;
+                    self.phase = (self.phase + BEEP_FREQUENCY / rate) % 1.0;
+                    high
+                }
+            };
+            *sample = if high { self.volume } else { -self.volume };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silent_when_not_beeping() {
+        let mut gen = AudioGenerator::new(44100);
+        let mut buf = [1.0; 64];
+        gen.fill(&AudioState::default(), &mut buf);
+        assert!(buf.iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn test_square_wave() {
+        let mut gen = AudioGenerator::new(880);
+        let state = AudioState {
+            beeping: true,
+            ..AudioState::default()
+        };
+        let mut buf = [0.0; 4];
+        gen.fill(&state, &mut buf);
+        assert_eq!(buf, [0.25, -0.25, 0.25, -0.25]);
+    }
+
+    #[test]
+    fn test_pattern() {
+        let mut gen = AudioGenerator::new(4000);
+        let mut pattern = [0; PATTERN_SIZE];
+        pattern[0] = 0b1010_0000;
+        let state = AudioState {
+            beeping: true,
+            pattern: Some(pattern),
+            pitch: DEFAULT_PITCH,
+        };
+        let mut buf = [0.0; 4];
+        gen.fill(&state, &mut buf);
+        assert_eq!(buf, [0.25, -0.25, 0.25, -0.25]);
+    }
+}
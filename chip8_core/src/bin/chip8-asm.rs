@@ -0,0 +1,72 @@
+use chip8_core::asm::assemble_file;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const USAGE: &str = "usage: chip8-asm [-o out.ch8] [--symbols out.sym] path/to/source.asm";
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    let opts = match parse_args(&args[1..]) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        }
+    };
+
+    let assembly = match assemble_file(&opts.input) {
+        Ok(assembly) => assembly,
+        Err(errors) => {
+            for err in &errors {
+                eprintln!("{}", err);
+            }
+            process::exit(1);
+        }
+    };
+    if let Err(err) = fs::write(&opts.output, &assembly.rom) {
+        eprintln!("could not write {}: {}", opts.output.display(), err);
+        process::exit(1);
+    }
+    if let Some(path) = &opts.symbols {
+        if let Err(err) = fs::write(path, assembly.symbol_map()) {
+            eprintln!("could not write {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    }
+}
+
+struct Options {
+    input: PathBuf,
+    output: PathBuf,
+    symbols: Option<PathBuf>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut symbols = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Some(PathBuf::from(args.next().ok_or("-o expects a path")?));
+            }
+            "--symbols" => {
+                symbols = Some(PathBuf::from(
+                    args.next().ok_or("--symbols expects a path")?,
+                ));
+            }
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let input: PathBuf = input.ok_or(USAGE)?;
+    let output = output.unwrap_or_else(|| Path::new(&input).with_extension("ch8"));
+    Ok(Options {
+        input,
+        output,
+        symbols,
+    })
+}
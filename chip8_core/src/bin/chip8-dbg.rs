@@ -0,0 +1,440 @@
+use chip8_core::*;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::net::TcpListener;
+use std::process;
+
+const TICKS_PER_FRAME: usize = 20;
+// `continue` gives control back after this many frames so looping ROMs can't hang the prompt
+const MAX_CONTINUE_FRAMES: usize = 600;
+const USAGE: &str = "usage: chip8-dbg [--platform chip8|schip|xo] [--quirks vip|schip|xo|modern] \
+                     [--seed n] [--gdb port] path/to/rom";
+const HELP: &str = "\
+break [addr]         set a breakpoint, or list them
+delete addr          remove a breakpoint
+watch loc            stop when loc is written (loc: v0-vf, i, dt, st or an address)
+rwatch loc           stop when loc is read
+awatch loc           stop when loc is read or written
+unwatch loc          remove a watchpoint
+step [n]             execute n instructions (s)
+next                 step over subroutine calls (n)
+finish               run until the current subroutine returns
+until addr           run until pc reaches addr
+continue [frames]    run until something stops execution (c)
+regs                 show registers
+x/Nb addr            dump N bytes of memory
+disas [addr] [n]     disassemble n instructions
+stack                show the call stack
+set reg value        change v0-vf, i, pc, dt or st
+screen               draw the display as text
+keys k down|up       press or release key k
+quit                 leave the debugger (q)";
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    let (opts, mut dbg) =
+        match parse_args(&args[1..]).and_then(|opts| load(&opts).map(|dbg| (opts, dbg))) {
+            Ok(loaded) => loaded,
+            Err(msg) => {
+                eprintln!("{}", msg);
+                process::exit(2);
+            }
+        };
+    if let Some(port) = opts.gdb_port {
+        if let Err(err) = serve_gdb(dbg, port) {
+            eprintln!("gdb stub: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut repl = Repl {
+        frame_ticks: 0,
+        last: String::new(),
+    };
+    repl.show_pc(&dbg);
+    let stdin = io::stdin();
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        // an empty line repeats the previous command, like gdb
+        let line = match line.trim() {
+            "" => repl.last.clone(),
+            line => line.to_string(),
+        };
+        repl.last = line.clone();
+        match repl.command(&mut dbg, &line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(msg) => println!("{}", msg),
+        }
+    }
+}
+
+struct Options {
+    path: String,
+    platform: Platform,
+    quirks: Quirks,
+    seed: Option<u64>,
+    gdb_port: Option<u16>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut platform = Platform::default();
+    let mut quirks = None;
+    let mut seed = None;
+    let mut gdb_port = None;
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                platform = args
+                    .next()
+                    .and_then(|name| Platform::from_name(name))
+                    .ok_or("unknown platform, expected chip8, schip or xo")?;
+            }
+            "--quirks" => {
+                quirks = Some(
+                    args.next()
+                        .and_then(|name| Quirks::preset(name))
+                        .ok_or("unknown quirks profile, expected vip, schip, xo or modern")?,
+                );
+            }
+            "--seed" => {
+                seed = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or("--seed expects an unsigned integer")?,
+                );
+            }
+            "--gdb" => {
+                gdb_port = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or("--gdb expects a port number")?,
+                );
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+    let quirks = quirks.unwrap_or_else(|| platform.default_quirks());
+    Ok(Options {
+        path,
+        platform,
+        quirks,
+        seed,
+        gdb_port,
+    })
+}
+
+fn load(opts: &Options) -> Result<Debugger, String> {
+    let mut rom = fs::read(&opts.path).map_err(|err| format!("{}: {}", opts.path, err))?;
+    if opts.path.ends_with(".8o") {
+        let source = String::from_utf8_lossy(&rom);
+        rom = octo::compile(&source, opts.platform)
+            .map_err(|err| format!("{}:{}", opts.path, err))?
+            .rom;
+    }
+    let mut emu = Emu::with_platform(opts.platform, opts.quirks);
+    if let Some(seed) = opts.seed {
+        emu.reseed(seed);
+    }
+    emu.load(&rom).map_err(|err| format!("{}: {}", opts.path, err))?;
+    Ok(Debugger::new(emu))
+}
+
+fn serve_gdb(dbg: Debugger, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("waiting for gdb on 127.0.0.1:{}", port);
+    let (stream, peer) = listener.accept()?;
+    println!("gdb connected from {}", peer);
+    gdb::GdbStub::new(dbg).serve(stream)
+}
+
+fn parse_num(text: &str) -> Result<usize, String> {
+    let parsed = match text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("not a number: {}", text))
+}
+
+// A number that has to fit in a register or address, such as u8 or u16.
+fn parse_int<T: TryFrom<usize>>(text: &str) -> Result<T, String> {
+    T::try_from(parse_num(text)?).map_err(|_| format!("value out of range: {}", text))
+}
+
+fn parse_location(text: &str) -> Result<Location, String> {
+    let lower = text.to_ascii_lowercase();
+    match lower.as_str() {
+        "i" => return Ok(Location::I),
+        "dt" => return Ok(Location::Dt),
+        "st" => return Ok(Location::St),
+        _ => {}
+    }
+    if let Some(reg) = lower.strip_prefix('v') {
+        if reg.len() == 1 {
+            if let Ok(x) = u8::from_str_radix(reg, 16) {
+                return Ok(Location::V(x));
+            }
+        }
+    }
+    Ok(Location::Ram(parse_int(text)?))
+}
+
+struct Repl {
+    // instructions run since the timers last ticked
+    frame_ticks: usize,
+    last: String,
+}
+
+impl Repl {
+    // returns false to quit
+    fn command(&mut self, dbg: &mut Debugger, line: &str) -> Result<bool, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            return Ok(true);
+        }
+        let arg = |n: usize| {
+            words
+                .get(n)
+                .copied()
+                .ok_or_else(|| format!("{} needs more arguments, see help", words[0]))
+        };
+        match words[0] {
+            "help" | "h" => println!("{}", HELP),
+            "quit" | "q" => return Ok(false),
+            "break" | "b" if words.len() == 1 => {
+                for addr in dbg.breakpoints() {
+                    println!("breakpoint at {:#05X}", addr);
+                }
+            }
+            "break" | "b" => {
+                let addr = parse_int(arg(1)?)?;
+                dbg.add_breakpoint(addr);
+                println!("breakpoint at {:#05X}", addr);
+            }
+            "delete" | "d" => {
+                let addr = parse_int(arg(1)?)?;
+                if !dbg.remove_breakpoint(addr) {
+                    return Err(format!("no breakpoint at {:#05X}", addr));
+                }
+            }
+            "watch" | "rwatch" | "awatch" => {
+                let location = parse_location(arg(1)?)?;
+                let kind = match words[0] {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::ReadWrite,
+                };
+                dbg.add_watchpoint(location, kind);
+                println!("watching {}", location);
+            }
+            "unwatch" => {
+                let location = parse_location(arg(1)?)?;
+                if !dbg.remove_watchpoint(location) {
+                    return Err(format!("{} is not watched", location));
+                }
+            }
+            "step" | "s" => {
+                let count = words.get(1).map(|n| parse_num(n)).transpose()?.unwrap_or(1);
+                for _ in 0..count {
+                    dbg.step_into();
+                    if self.run(dbg, 1) != Some(StopReason::Step) {
+                        break;
+                    }
+                }
+                self.show_pc(dbg);
+            }
+            "next" | "n" => {
+                dbg.step_over();
+                self.run(dbg, MAX_CONTINUE_FRAMES);
+                self.show_pc(dbg);
+            }
+            "finish" => {
+                if !dbg.step_out() {
+                    return Err("not in a subroutine".to_string());
+                }
+                self.run(dbg, MAX_CONTINUE_FRAMES);
+                self.show_pc(dbg);
+            }
+            "until" | "u" => {
+                dbg.run_to(parse_int(arg(1)?)?);
+                self.run(dbg, MAX_CONTINUE_FRAMES);
+                self.show_pc(dbg);
+            }
+            "continue" | "c" => {
+                let frames = words.get(1).map(|n| parse_num(n)).transpose()?;
+                dbg.resume();
+                self.run(dbg, frames.unwrap_or(MAX_CONTINUE_FRAMES));
+                self.show_pc(dbg);
+            }
+            "regs" | "r" => {
+                let emu = dbg.emu();
+                for (idx, val) in emu.get_v_reg().iter().enumerate() {
+                    print!(
+                        "V{:X}={:02X}{}",
+                        idx,
+                        val,
+                        if idx % 8 == 7 { "\n" } else { " " }
+                    );
+                }
+                println!(
+                    "I={:04X} PC={:04X} SP={:X} DT={:02X} ST={:02X}",
+                    emu.get_i_reg(),
+                    emu.get_pc(),
+                    emu.get_sp(),
+                    emu.get_dt(),
+                    emu.get_st()
+                );
+                match emu.key_wait() {
+                    Some(KeyWait::Press { x }) => println!("waiting for a key press into V{:X}", x),
+                    Some(KeyWait::Release { x, key }) => {
+                        println!("waiting for key {:X} to be released into V{:X}", key, x)
+                    }
+                    None => {}
+                }
+            }
+            cmd if cmd == "x" || cmd.starts_with("x/") => {
+                let count = match cmd.strip_prefix("x/") {
+                    Some(fmt) => parse_num(fmt.trim_end_matches('b'))?,
+                    None => 16,
+                };
+                let start = parse_num(arg(1)?)?;
+                let ram = dbg.emu().get_ram();
+                if start >= ram.len() {
+                    return Err(format!("{:#X} is past the end of RAM", start));
+                }
+                let end = start.saturating_add(count).min(ram.len());
+                for row in (start..end).step_by(16) {
+                    let bytes: Vec<String> = ram[row..end.min(row + 16)]
+                        .iter()
+                        .map(|b| format!("{:02X}", b))
+                        .collect();
+                    println!("{:04X}: {}", row, bytes.join(" "));
+                }
+            }
+            "disas" => {
+                let emu = dbg.emu();
+                let start = match words.get(1) {
+                    Some(addr) => parse_num(addr)?,
+                    None => emu.get_pc() as usize,
+                };
+                let count = words
+                    .get(2)
+                    .map(|n| parse_num(n))
+                    .transpose()?
+                    .unwrap_or(10);
+                let ram = emu.get_ram();
+                if start >= ram.len() {
+                    return Err(format!("{:#X} is past the end of RAM", start));
+                }
+                // up to 4 bytes per instruction
+                let end = start.saturating_add(count.saturating_mul(4)).min(ram.len());
+                for line in disassemble(&ram[start..end], start as u16)
+                    .iter()
+                    .take(count)
+                {
+                    let marker = if line.addr == emu.get_pc() {
+                        "=>"
+                    } else {
+                        "  "
+                    };
+                    println!("{} {}", marker, line);
+                }
+            }
+            "stack" | "bt" => {
+                let state = dbg.emu().machine_state();
+                println!("#0 {:04X}", state.pc);
+                for (depth, ret) in state.call_stack().iter().rev().enumerate() {
+                    println!("#{} {:04X}", depth + 1, ret);
+                }
+            }
+            "set" if arg(1)?.eq_ignore_ascii_case("pc") => {
+                dbg.emu_mut().set_pc(parse_int(arg(2)?)?);
+            }
+            "set" => {
+                let location = parse_location(arg(1)?)?;
+                let value = arg(2)?;
+                let emu = dbg.emu_mut();
+                match location {
+                    Location::V(x) => emu.set_v_reg(x as usize, parse_int(value)?),
+                    Location::I => emu.set_i_reg(parse_int(value)?),
+                    Location::Dt => emu.set_dt(parse_int(value)?),
+                    Location::St => emu.set_st(parse_int(value)?),
+                    Location::Ram(addr) => {
+                        emu.write_ram(addr as usize, parse_int(value)?)
+                            .map_err(|err| err.to_string())?;
+                    }
+                }
+            }
+            "screen" => {
+                let emu = dbg.emu();
+                let (width, _) = emu.get_display_size();
+                for row in emu.get_display().chunks(width) {
+                    let line: String = row.iter().map(|p| if *p { '#' } else { '.' }).collect();
+                    println!("{}", line);
+                }
+            }
+            "keys" => {
+                // a single hex digit, like the keypad
+                let key = usize::from_str_radix(arg(1)?, 16)
+                    .ok()
+                    .filter(|key| *key < 16)
+                    .ok_or("keys go from 0 to f")?;
+                let pressed = match arg(2)? {
+                    "down" => true,
+                    "up" => false,
+                    other => return Err(format!("expected down or up, found {}", other)),
+                };
+                dbg.emu_mut()
+                    .keypress(key, pressed)
+                    .map_err(|err| err.to_string())?;
+            }
+            other => return Err(format!("unknown command {}, try help", other)),
+        }
+        Ok(true)
+    }
+
+    // runs frame by frame, ticking timers in between, for at most `frames` frames
+    fn run(&mut self, dbg: &mut Debugger, frames: usize) -> Option<StopReason> {
+        for _ in 0..frames * TICKS_PER_FRAME {
+            let ticks = dbg.emu().tick_count();
+            let stop = dbg.run(1);
+            // breakpoints stop before the instruction runs, steps and watchpoints after it
+            if stop.is_none() || dbg.emu().tick_count() != ticks {
+                self.frame_ticks += 1;
+                if self.frame_ticks == TICKS_PER_FRAME {
+                    self.frame_ticks = 0;
+                    dbg.emu_mut().tick_timers();
+                }
+            }
+            if let Some(reason) = stop {
+                if reason != StopReason::Step {
+                    println!("{}", reason);
+                }
+                return Some(reason);
+            }
+        }
+        println!("paused after {} frames", frames);
+        None
+    }
+
+    fn show_pc(&self, dbg: &Debugger) {
+        let emu = dbg.emu();
+        let pc = emu.get_pc() as usize;
+        let ram = emu.get_ram();
+        let end = (pc + 4).min(ram.len());
+        if let Some(line) = disassemble(&ram[pc.min(end)..end], pc as u16).first() {
+            println!("=> {}", line);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use chip8_core::image::Frame;
+use chip8_core::*;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::iter;
+use std::process;
+use std::time::Duration;
+
+const DEFAULT_FRAMES: usize = 60;
+const DEFAULT_TICKS_PER_FRAME: usize = 20;
+// one 60 Hz frame, so the scheduler runs a frame's instructions then the timers
+const FRAME_TIME: Duration = Duration::from_nanos(16_666_667);
+const USAGE: &str =
+    "usage: chip8-headless [--platform chip8|schip|xo] [--quirks vip|schip|xo|modern] \
+                     [--seed n] [--frames n] [--ticks-per-frame n] [--key frame:key:down|up]... \
+                     [--keys timeline.txt] [--screenshot out.pbm|out.png] [--dump state.json] \
+                     [--trace trace.log] [--record out.movie | --play in.movie] \
+                     path/to/rom";
+
+// exit codes, so CI can tell a crashing ROM from a bad command line
+const EXIT_FAULT: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    let opts = match parse_args(&args[1..]) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let mut emu = match load(&opts) {
+        Ok(emu) => emu,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    if let Some(path) = &opts.trace {
+        match File::create(path) {
+            Ok(file) => emu.trace_to(BufWriter::new(file)),
+            Err(err) => {
+                eprintln!("could not write {}: {}", path, err);
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+
+    // a movie replays until its last event unless told otherwise
+    let frames = match (opts.frames, &opts.movie) {
+        (Some(frames), _) => frames,
+        (None, Some(movie)) => movie.last_frame() as usize + 1,
+        (None, None) => DEFAULT_FRAMES,
+    };
+    let mut scheduler = Scheduler::new(opts.ips);
+    let mut events = opts.keys.iter().peekable();
+    let mut fault = None;
+    let mut frames_run = 0;
+    for frame in 0..frames {
+        let result = iter::from_fn(|| events.next_if(|event| event.frame <= frame))
+            .try_for_each(|event| emu.keypress(event.key, event.pressed))
+            .and_then(|()| scheduler.advance(&mut emu, FRAME_TIME));
+        if let Err(err) = result {
+            fault = Some(err);
+            break;
+        }
+        frames_run = frame + 1;
+        if emu.has_exited() {
+            break;
+        }
+    }
+    if let Err(err) = emu.stop_trace() {
+        eprintln!("could not write trace: {}", err);
+        process::exit(EXIT_USAGE);
+    }
+    if let Some(movie) = emu.stop_recording() {
+        write_or_exit(opts.record.as_ref().unwrap(), movie.to_text().as_bytes());
+    }
+
+    if let Some(path) = &opts.screenshot {
+        let frame = Frame::capture(&emu);
+        let data = if path.ends_with(".png") {
+            frame.to_png()
+        } else {
+            frame.to_pbm().into_bytes()
+        };
+        write_or_exit(path, &data);
+    }
+    if let Some(path) = &opts.dump {
+        write_or_exit(path, dump_json(&emu, frames_run, fault).as_bytes());
+    }
+    if let Some(err) = fault {
+        eprintln!("fault after {} frames: {}", frames_run, err);
+        process::exit(EXIT_FAULT);
+    }
+}
+
+struct KeyEvent {
+    frame: usize,
+    key: usize,
+    pressed: bool,
+}
+
+struct Options {
+    path: String,
+    platform: Platform,
+    quirks: Quirks,
+    seed: Option<u64>,
+    frames: Option<usize>,
+    ips: u32,
+    keys: Vec<KeyEvent>,
+    screenshot: Option<String>,
+    dump: Option<String>,
+    trace: Option<String>,
+    record: Option<String>,
+    movie: Option<Movie>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut platform = Platform::default();
+    let mut quirks = None;
+    let mut seed = None;
+    let mut frames = None;
+    let mut ticks_per_frame = DEFAULT_TICKS_PER_FRAME;
+    let mut keys = Vec::new();
+    let mut screenshot = None;
+    let mut dump = None;
+    let mut trace = None;
+    let mut record = None;
+    let mut movie = None;
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                platform = args
+                    .next()
+                    .and_then(|name| Platform::from_name(name))
+                    .ok_or("unknown platform, expected chip8, schip or xo")?;
+            }
+            "--quirks" => {
+                quirks = Some(
+                    args.next()
+                        .and_then(|name| Quirks::preset(name))
+                        .ok_or("unknown quirks profile, expected vip, schip, xo or modern")?,
+                );
+            }
+            "--seed" => {
+                seed = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or("--seed expects an unsigned integer")?,
+                );
+            }
+            "--frames" => {
+                frames = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or("--frames expects a number of frames")?,
+                );
+            }
+            "--ticks-per-frame" => {
+                ticks_per_frame = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or("--ticks-per-frame expects a number of instructions")?;
+            }
+            "--key" => {
+                let event = args.next().ok_or("--key expects frame:key:down|up")?;
+                keys.push(parse_key_event(&event.replace(':', " "))?);
+            }
+            "--keys" => {
+                let file = args.next().ok_or("--keys expects a path")?;
+                let text = fs::read_to_string(file).map_err(|err| format!("{}: {}", file, err))?;
+                for (idx, line) in text.lines().enumerate() {
+                    let line = line.split('#').next().unwrap_or("").trim();
+                    if !line.is_empty() {
+                        let event = parse_key_event(line)
+                            .map_err(|err| format!("{}:{}: {}", file, idx + 1, err))?;
+                        keys.push(event);
+                    }
+                }
+            }
+            "--screenshot" => {
+                screenshot = Some(args.next().ok_or("--screenshot expects a path")?.clone());
+            }
+            "--dump" => dump = Some(args.next().ok_or("--dump expects a path")?.clone()),
+            "--trace" => trace = Some(args.next().ok_or("--trace expects a path")?.clone()),
+            "--record" => record = Some(args.next().ok_or("--record expects a path")?.clone()),
+            "--play" => {
+                let file = args.next().ok_or("--play expects a path")?;
+                let text = fs::read_to_string(file).map_err(|err| format!("{}: {}", file, err))?;
+                movie = Some(Movie::parse(&text).map_err(|err| format!("{}:{}", file, err))?);
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+    let mut quirks = quirks.unwrap_or_else(|| platform.default_quirks());
+    let mut ips = (ticks_per_frame * 60) as u32;
+    if let Some(movie) = &movie {
+        if !keys.is_empty() || record.is_some() {
+            return Err("--play can't be combined with --key, --keys or --record".to_string());
+        }
+        // the movie knows how the machine was set up when it was recorded
+        platform = movie.platform;
+        quirks = movie.quirks;
+        seed = Some(movie.seed);
+        ips = movie.ips;
+    }
+    // events apply in frame order, the stable sort keeps same-frame events as written
+    keys.sort_by_key(|event| event.frame);
+    Ok(Options {
+        path,
+        platform,
+        quirks,
+        seed,
+        frames,
+        ips,
+        keys,
+        screenshot,
+        dump,
+        trace,
+        record,
+        movie,
+    })
+}
+
+// "FRAME KEY down|up", the key in hex
+fn parse_key_event(text: &str) -> Result<KeyEvent, String> {
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    let [frame, key, action] = fields[..] else {
+        return Err(format!("expected 'frame key down|up', found '{}'", text));
+    };
+    let frame = frame
+        .parse()
+        .map_err(|_| format!("bad frame number '{}'", frame))?;
+    let key = usize::from_str_radix(key, 16)
+        .ok()
+        .filter(|key| *key < 16)
+        .ok_or_else(|| format!("bad key '{}', expected 0-f", key))?;
+    let pressed = match action {
+        "down" => true,
+        "up" => false,
+        _ => return Err(format!("expected down or up, found '{}'", action)),
+    };
+    Ok(KeyEvent {
+        frame,
+        key,
+        pressed,
+    })
+}
+
+fn load(opts: &Options) -> Result<Emu, String> {
+    let mut rom = fs::read(&opts.path).map_err(|err| format!("{}: {}", opts.path, err))?;
+    if opts.path.ends_with(".8o") {
+        let source = String::from_utf8_lossy(&rom);
+        rom = octo::compile(&source, opts.platform)
+            .map_err(|err| format!("{}:{}", opts.path, err))?
+            .rom;
+    }
+    if let Some(movie) = &opts.movie {
+        return movie
+            .boot(&rom)
+            .map_err(|err| format!("{}: {}", opts.path, err));
+    }
+    let mut emu = Emu::with_platform(opts.platform, opts.quirks);
+    // a recording has to name its seed to be replayable
+    let seed = match (opts.seed, &opts.record) {
+        (Some(seed), _) => Some(seed),
+        (None, Some(_)) => Some(random_seed()),
+        (None, None) => None,
+    };
+    if let Some(seed) = seed {
+        emu.reseed(seed);
+    }
+    emu.load(&rom).map_err(|err| format!("{}: {}", opts.path, err))?;
+    if let Some(seed) = seed.filter(|_| opts.record.is_some()) {
+        emu.start_recording(Movie::new(&rom, opts.platform, opts.quirks, seed, opts.ips));
+    }
+    Ok(emu)
+}
+
+fn write_or_exit(path: &str, data: &[u8]) {
+    if let Err(err) = fs::write(path, data) {
+        eprintln!("could not write {}: {}", path, err);
+        process::exit(EXIT_USAGE);
+    }
+}
+
+fn dump_json(emu: &Emu, frames: usize, fault: Option<EmuError>) -> String {
+    let state = emu.machine_state();
+    let list = |values: Vec<String>| format!("[{}]", values.join(", "));
+    let fault = match fault {
+        Some(err) => format!("\"{}\"", err),
+        None => "null".to_string(),
+    };
+    let ram: String = state.ram.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{{\n  \"platform\": \"{}\",\n  \"frames\": {},\n  \"fault\": {},\n  \"exited\": {},\n  \
+         \"pc\": {},\n  \"i\": {},\n  \"sp\": {},\n  \"dt\": {},\n  \"st\": {},\n  \
+         \"v\": {},\n  \"stack\": {},\n  \"keys\": {},\n  \"display\": {{ \"width\": {}, \"height\": {} }},\n  \
+         \"ram\": \"{}\"\n}}\n",
+        state.platform.name(),
+        frames,
+        fault,
+        emu.has_exited(),
+        state.pc,
+        state.i_reg,
+        state.sp,
+        state.dt,
+        state.st,
+        list(state.v_reg.iter().map(|v| v.to_string()).collect()),
+        list(state.call_stack().iter().map(|a| a.to_string()).collect()),
+        list(state.keys.iter().map(|k| k.to_string()).collect()),
+        state.display_size.0,
+        state.display_size.1,
+        ram,
+    )
+}
@@ -0,0 +1,482 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::{decode, Emu, EmuError, Instruction};
+
+/// Something a watchpoint can observe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    Ram(u16),
+    V(u8),
+    I,
+    Dt,
+    St,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Ram(addr) => write!(f, "[{:#05X}]", addr),
+            Location::V(x) => write!(f, "V{:X}", x),
+            Location::I => write!(f, "I"),
+            Location::Dt => write!(f, "DT"),
+            Location::St => write!(f, "ST"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Which accesses trigger a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::ReadWrite, _)
+                | (WatchKind::Read, Access::Read)
+                | (WatchKind::Write, Access::Write)
+        )
+    }
+}
+
+/// Why `Debugger::run` gave control back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested step, step over, step out or run-to finished.
+    Step,
+    /// About to execute the instruction at this breakpoint.
+    Breakpoint(u16),
+    /// The instruction at `pc` touched a watched location.
+    Watchpoint {
+        pc: u16,
+        location: Location,
+        access: Access,
+    },
+    Fault(EmuError),
+    /// The ROM ran 00FD.
+    Exited,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "step finished"),
+            StopReason::Breakpoint(addr) => write!(f, "breakpoint at {:#05X}", addr),
+            StopReason::Watchpoint {
+                pc,
+                location,
+                access,
+            } => {
+                let verb = match access {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                };
+                write!(f, "{} of {} at {:#05X}", verb, location, pc)
+            }
+            StopReason::Fault(err) => write!(f, "fault: {}", err),
+            StopReason::Exited => write!(f, "program exited"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Goal {
+    Free,
+    Step,
+    // stop once the stack is no deeper than this
+    Return(u16),
+    RunTo(u16),
+}
+
+/// Breakpoints, watchpoints and stepping around an `Emu`. The frontend decides
+/// how many instructions to run per call, so timers and input stay its business.
+#[derive(Debug)]
+pub struct Debugger {
+    emu: Emu,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(Location, WatchKind)>,
+    goal: Goal,
+    // pc we last stopped at, so resuming does not hit the same breakpoint again
+    resume_from: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new(emu: Emu) -> Self {
+        Self {
+            emu,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            goal: Goal::Free,
+            resume_from: None,
+        }
+    }
+
+    pub fn emu(&self) -> &Emu {
+        &self.emu
+    }
+
+    pub fn emu_mut(&mut self) -> &mut Emu {
+        &mut self.emu
+    }
+
+    pub fn into_emu(self) -> Emu {
+        self.emu
+    }
+
+    /// Returns false if there already was a breakpoint at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Watches `location`, replacing any earlier watchpoint on it.
+    pub fn add_watchpoint(&mut self, location: Location, kind: WatchKind) {
+        self.remove_watchpoint(location);
+        self.watchpoints.push((location, kind));
+    }
+
+    pub fn remove_watchpoint(&mut self, location: Location) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|(loc, _)| *loc != location);
+        self.watchpoints.len() != before
+    }
+
+    pub fn watchpoints(&self) -> &[(Location, WatchKind)] {
+        &self.watchpoints
+    }
+
+    /// Next `run` stops after one instruction.
+    pub fn step_into(&mut self) {
+        self.goal = Goal::Step;
+    }
+
+    /// Like `step_into`, but a 2NNN call runs until its matching return.
+    pub fn step_over(&mut self) {
+        self.goal = match self.current_instruction() {
+            Instruction::Call(_) => Goal::Return(self.emu.sp),
+            _ => Goal::Step,
+        };
+    }
+
+    /// Next `run` stops once the current subroutine returns. Returns false,
+    /// leaving the goal alone, if the top level is running.
+    pub fn step_out(&mut self) -> bool {
+        match self.emu.sp {
+            0 => false,
+            sp => {
+                self.goal = Goal::Return(sp - 1);
+                true
+            }
+        }
+    }
+
+    /// Next `run` stops before executing the instruction at `addr`.
+    pub fn run_to(&mut self, addr: u16) {
+        self.goal = Goal::RunTo(addr);
+    }
+
+    /// Forgets any pending step so `run` only stops at breakpoints and watchpoints.
+    pub fn resume(&mut self) {
+        self.goal = Goal::Free;
+    }
+
+    /// Executes up to `max_ticks` instructions towards the pending goal.
+    /// Returns None if the budget ran out first; calling again carries on.
+    pub fn run(&mut self, max_ticks: usize) -> Option<StopReason> {
+        for _ in 0..max_ticks {
+            if self.emu.exited {
+                return Some(self.stop(StopReason::Exited));
+            }
+            if self.emu.key_wait.is_some() {
+                // nothing executes until the frontend delivers a key, and the
+                // instruction at pc hasn't been reached yet, so don't skip its breakpoint
+                self.resume_from = None;
+                if self.goal == Goal::Step {
+                    return Some(self.stop(StopReason::Step));
+                }
+                continue;
+            }
+            let pc = self.emu.pc;
+            let resuming = self.resume_from.take() == Some(pc);
+            if !resuming {
+                if self.breakpoints.contains(&pc) {
+                    return Some(self.stop(StopReason::Breakpoint(pc)));
+                }
+                if self.goal == Goal::RunTo(pc) {
+                    return Some(self.stop(StopReason::Step));
+                }
+            }
+
+            let hit = self.watch_hit();
+            if let Err(err) = self.emu.tick() {
+                return Some(self.stop(StopReason::Fault(err)));
+            }
+            if let Some((location, access)) = hit {
+                return Some(self.stop(StopReason::Watchpoint {
+                    pc,
+                    location,
+                    access,
+                }));
+            }
+            let done = match self.goal {
+                Goal::Step => true,
+                Goal::Return(sp) => self.emu.sp <= sp,
+                Goal::Free | Goal::RunTo(_) => false,
+            };
+            if done {
+                return Some(self.stop(StopReason::Step));
+            }
+        }
+        None
+    }
+
+    fn stop(&mut self, reason: StopReason) -> StopReason {
+        self.goal = Goal::Free;
+        self.resume_from = Some(self.emu.pc);
+        reason
+    }
+
+    fn current_instruction(&self) -> Instruction {
+        let pc = self.emu.pc as usize;
+        match (self.emu.ram.get(pc), self.emu.ram.get(pc + 1)) {
+            (Some(hi), Some(lo)) => decode((*hi as u16) << 8 | *lo as u16),
+            _ => Instruction::Unknown(0),
+        }
+    }
+
+    fn watch_hit(&self) -> Option<(Location, Access)> {
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+        accesses(&self.emu, self.current_instruction())
+            .into_iter()
+            .find(|(location, access)| {
+                self.watchpoints
+                    .iter()
+                    .any(|(loc, kind)| loc == location && kind.matches(*access))
+            })
+    }
+}
+
+/// Registers and memory the next instruction will read and write, worked out
+/// from its decoding and the current machine state.
+fn accesses(emu: &Emu, instruction: Instruction) -> Vec<(Location, Access)> {
+    use Access::{Read, Write};
+    use Location::*;
+
+    let quirks = emu.quirks;
+    let i = emu.i_reg;
+    let ram = |start: u16, len: usize, access: Access| {
+        (0..len).map(move |offset| (Ram(start.wrapping_add(offset as u16)), access))
+    };
+    let mut out = Vec::new();
+    match instruction {
+        Instruction::SeByte(x, _) | Instruction::SneByte(x, _) => out.push((V(x), Read)),
+        Instruction::SeReg(x, y) | Instruction::SneReg(x, y) => {
+            out.extend([(V(x), Read), (V(y), Read)]);
+        }
+        Instruction::LdByte(x, _) | Instruction::Rnd(x, _) => out.push((V(x), Write)),
+        Instruction::AddByte(x, _) => out.extend([(V(x), Read), (V(x), Write)]),
+        Instruction::LdReg(x, y) => out.extend([(V(y), Read), (V(x), Write)]),
+        Instruction::Or(x, y) | Instruction::And(x, y) | Instruction::Xor(x, y) => {
+            out.extend([(V(x), Read), (V(y), Read), (V(x), Write)]);
+            if quirks.vf_reset {
+                out.push((V(0xF), Write));
+            }
+        }
+        Instruction::AddReg(x, y) | Instruction::Sub(x, y) | Instruction::Subn(x, y) => {
+            out.extend([(V(x), Read), (V(y), Read), (V(x), Write), (V(0xF), Write)]);
+        }
+        Instruction::Shr(x, y) | Instruction::Shl(x, y) => {
+            let src = if quirks.shift { x } else { y };
+            out.extend([(V(src), Read), (V(x), Write), (V(0xF), Write)]);
+        }
+        Instruction::LdI(_) | Instruction::LdILong => out.push((I, Write)),
+        Instruction::JpV0(nnn) => {
+            let x = if quirks.jump { (nnn >> 8) as u8 } else { 0 };
+            out.push((V(x), Read));
+        }
+        Instruction::Drw(x, y, n) => {
+            let big = n == 0 && emu.platform.supports_schip();
+            let bytes = if big { 32 } else { n as usize } * emu.selected_planes().count();
+            out.extend([(V(x), Read), (V(y), Read), (I, Read)]);
+            out.extend(ram(i, bytes, Read));
+            out.push((V(0xF), Write));
+        }
+        Instruction::Skp(x) | Instruction::Sknp(x) | Instruction::Pitch(x) => {
+            out.push((V(x), Read));
+        }
+        Instruction::Audio => {
+            out.push((I, Read));
+            out.extend(ram(i, 16, Read));
+        }
+        Instruction::LdVxDt(x) => out.extend([(Dt, Read), (V(x), Write)]),
+        // otherwise VX is written later, by the keypress that ends the wait
+        Instruction::LdVxK(x) if emu.quirks.key_wait_held && emu.keys.iter().any(|k| *k) => {
+            out.push((V(x), Write))
+        }
+        Instruction::LdDtVx(x) => out.extend([(V(x), Read), (Dt, Write)]),
+        Instruction::LdStVx(x) => out.extend([(V(x), Read), (St, Write)]),
+        Instruction::AddI(x) => out.extend([(I, Read), (V(x), Read), (I, Write)]),
+        Instruction::LdF(x) | Instruction::LdHf(x) => out.extend([(V(x), Read), (I, Write)]),
+        Instruction::LdB(x) => {
+            out.extend([(V(x), Read), (I, Read)]);
+            out.extend(ram(i, 3, Write));
+        }
+        Instruction::LdIVx(x) => {
+            out.extend((0..=x).map(|r| (V(r), Read)));
+            out.push((I, Read));
+            out.extend(ram(i, x as usize + 1, Write));
+            if quirks.memory_increment {
+                out.push((I, Write));
+            }
+        }
+        Instruction::LdVxI(x) => {
+            out.push((I, Read));
+            out.extend(ram(i, x as usize + 1, Read));
+            out.extend((0..=x).map(|r| (V(r), Write)));
+            if quirks.memory_increment {
+                out.push((I, Write));
+            }
+        }
+        Instruction::SaveRange(x, y) | Instruction::LoadRange(x, y) => {
+            let regs = Emu::vx_vy_range(x as u16, y as u16);
+            let save = matches!(instruction, Instruction::SaveRange(..));
+            let (reg_access, ram_access) = if save { (Read, Write) } else { (Write, Read) };
+            out.push((I, Read));
+            out.extend(regs.iter().map(|r| (V(*r as u8), reg_access)));
+            out.extend(ram(i, regs.len(), ram_access));
+        }
+        Instruction::LdRVx(x) => out.extend((0..=x).map(|r| (V(r), Read))),
+        Instruction::LdVxR(x) => out.extend((0..=x).map(|r| (V(r), Write))),
+        _ => {}
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debugger(rom: &[u8]) -> Debugger {
+        let mut emu = Emu::new();
+        emu.load(rom).unwrap();
+        Debugger::new(emu)
+    }
+
+    // 200: CALL 208, 202: ADD V0 1, 204: JP 204, 206: NOP
+    // 208: LD V1 5, 20A: CALL 20E, 20C: RET, 20E: LD V2 6, 210: RET
+    const CALLS: [u8; 18] = [
+        0x22, 0x08, 0x70, 0x01, 0x12, 0x04, 0x00, 0x00, 0x61, 0x05, 0x22, 0x0E, 0x00, 0xEE, 0x62,
+        0x06, 0x00, 0xEE,
+    ];
+
+    #[test]
+    fn test_breakpoints() {
+        let mut dbg = debugger(&CALLS);
+        dbg.add_breakpoint(0x20E);
+        assert_eq!(dbg.run(100), Some(StopReason::Breakpoint(0x20E)));
+        assert_eq!(dbg.emu().get_pc(), 0x20E);
+        // resuming steps off the breakpoint instead of stopping on it again
+        assert_eq!(dbg.run(100), None);
+        assert_eq!(dbg.emu().get_v_reg()[2], 6);
+        assert!(dbg.remove_breakpoint(0x20E));
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let mut dbg = debugger(&CALLS);
+        dbg.step_over();
+        assert_eq!(dbg.run(100), Some(StopReason::Step));
+        assert_eq!(dbg.emu().get_pc(), 0x202);
+        assert_eq!(dbg.emu().get_v_reg()[1..3], [5, 6]);
+
+        let mut dbg = debugger(&CALLS);
+        dbg.run_to(0x20E);
+        assert_eq!(dbg.run(100), Some(StopReason::Step));
+        assert!(dbg.step_out());
+        assert_eq!(dbg.run(100), Some(StopReason::Step));
+        assert_eq!(dbg.emu().get_pc(), 0x20C);
+        dbg.step_into();
+        assert_eq!(dbg.run(100), Some(StopReason::Step));
+        assert_eq!(dbg.emu().get_pc(), 0x202);
+        assert!(!dbg.step_out());
+    }
+
+    #[test]
+    fn test_step_over_budget() {
+        let mut dbg = debugger(&CALLS);
+        dbg.step_over();
+        assert_eq!(dbg.run(2), None);
+        assert_eq!(dbg.run(100), Some(StopReason::Step));
+        assert_eq!(dbg.emu().get_pc(), 0x202);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        // LD I 0x300, LD V0 7, LD [I] V0, LD V1 [I]
+        let mut dbg = debugger(&[0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0xF1, 0x65]);
+        dbg.add_watchpoint(Location::Ram(0x300), WatchKind::Write);
+        dbg.add_watchpoint(Location::V(1), WatchKind::ReadWrite);
+        assert_eq!(
+            dbg.run(100),
+            Some(StopReason::Watchpoint {
+                pc: 0x204,
+                location: Location::Ram(0x300),
+                access: Access::Write,
+            })
+        );
+        assert_eq!(dbg.emu().get_ram()[0x300], 7);
+        assert_eq!(
+            dbg.run(100),
+            Some(StopReason::Watchpoint {
+                pc: 0x206,
+                location: Location::V(1),
+                access: Access::Write,
+            })
+        );
+    }
+
+    #[test]
+    fn test_fault() {
+        let mut dbg = debugger(&[0x00, 0xEE]);
+        assert_eq!(
+            dbg.run(10),
+            Some(StopReason::Fault(EmuError::StackUnderflow))
+        );
+    }
+
+    #[test]
+    fn test_key_wait_blocks_breakpoints() {
+        // LD V0, K; LD V1, 1
+        let mut dbg = debugger(&[0xF0, 0x0A, 0x61, 0x01]);
+        dbg.add_breakpoint(0x202);
+        dbg.step_into();
+        assert_eq!(dbg.run(10), Some(StopReason::Step));
+        assert!(dbg.emu().key_wait().is_some());
+        // the pc already points past FX0A, but that instruction hasn't been reached
+        assert_eq!(dbg.run(100), None);
+        dbg.emu_mut().keypress(4, true).unwrap();
+        dbg.emu_mut().keypress(4, false).unwrap();
+        assert_eq!(dbg.run(100), Some(StopReason::Breakpoint(0x202)));
+        assert_eq!(dbg.emu().get_v_reg()[0], 4);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::HIRES_HEIGHT;
+
+/// Pixel bounds of a changed area, in display coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// The parts of the display touched since the last `Emu::take_dirty`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DirtyRegion {
+    // bit y is set when row y changed; 64 bits cover the hires screen
+    rows: u64,
+    left: usize,
+    right: usize,
+}
+
+const _: () = assert!(HIRES_HEIGHT <= u64::BITS as usize);
+
+impl DirtyRegion {
+    /// True when nothing changed, so the last presented frame is still current.
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    pub fn contains_row(&self, y: usize) -> bool {
+        y < HIRES_HEIGHT && self.rows & (1 << y) != 0
+    }
+
+    /// Changed rows, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..HIRES_HEIGHT).filter(|y| self.contains_row(*y))
+    }
+
+    /// Smallest rectangle holding every change; sprites wrapping around an
+    /// edge make it span the whole width or height.
+    pub fn bounds(&self) -> Option<Rect> {
+        if self.is_empty() {
+            return None;
+        }
+        let top = self.rows.trailing_zeros() as usize;
+        let bottom = u64::BITS as usize - self.rows.leading_zeros() as usize;
+        Some(Rect {
+            x: self.left,
+            y: top,
+            width: self.right - self.left,
+            height: bottom - top,
+        })
+    }
+
+    pub(crate) fn mark(&mut self, x: usize, y: usize) {
+        if self.is_empty() {
+            self.left = x;
+            self.right = x + 1;
+        } else {
+            self.left = self.left.min(x);
+            self.right = self.right.max(x + 1);
+        }
+        self.rows |= 1 << y;
+    }
+
+    pub(crate) fn mark_all(&mut self, (width, height): (usize, usize)) {
+        self.rows = u64::MAX >> (u64::BITS as usize - height);
+        self.left = 0;
+        self.right = width;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Emu, Platform, Quirks, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    use super::*;
+
+    #[test]
+    fn test_draw_marks_sprite_bounds() {
+        let mut emu = Emu::new();
+        // LD V0, 10; LD V1, 4; LD I, font 0; DRW V0, V1, 5; DRW V0, V1, 5
+        emu.load(&[0x60, 0x0A, 0x61, 0x04, 0xA0, 0x00, 0xD0, 0x15, 0xD0, 0x15]).unwrap();
+        assert_eq!(
+            emu.take_dirty().bounds(),
+            Some(Rect {
+                x: 0,
+                y: 0,
+                width: SCREEN_WIDTH,
+                height: SCREEN_HEIGHT
+            })
+        );
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        assert!(emu.take_dirty().is_empty());
+
+        emu.tick().unwrap();
+        let dirty = emu.take_dirty();
+        assert_eq!(dirty.rows().collect::<Vec<_>>(), [4, 5, 6, 7, 8]);
+        assert_eq!(
+            dirty.bounds(),
+            Some(Rect {
+                x: 10,
+                y: 4,
+                width: 4,
+                height: 5
+            })
+        );
+        assert!(emu.take_dirty().is_empty());
+        // erasing the sprite is a change too
+        emu.tick().unwrap();
+        assert_eq!(emu.take_dirty(), dirty);
+    }
+
+    #[test]
+    fn test_wrapping_and_full_screen_changes() {
+        let mut emu = Emu::with_platform(Platform::SuperChip, Quirks::modern());
+        // LD V0, 62; LD I, font 0; DRW V0, V0, 1; HIGH; SCD 1
+        emu.load(&[0x60, 0x3E, 0xA0, 0x00, 0xD0, 0x01, 0x00, 0xFF, 0x00, 0xC1]).unwrap();
+        emu.take_dirty();
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        let dirty = emu.take_dirty();
+        assert_eq!(dirty.rows().collect::<Vec<_>>(), [30]);
+        assert_eq!(dirty.bounds().unwrap().width, SCREEN_WIDTH);
+
+        emu.tick().unwrap();
+        assert_eq!(emu.take_dirty().bounds().unwrap().height, 64);
+        emu.tick().unwrap();
+        assert!(emu.take_dirty().contains_row(63));
+    }
+}
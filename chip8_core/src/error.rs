@@ -0,0 +1,30 @@
+use std::error::Error;
+use std::fmt;
+
+/// Faults raised while executing a ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmuError {
+    UnknownOpcode { pc: u16, op: u16 },
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds { addr: usize },
+    InvalidKey { key: usize },
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::UnknownOpcode { pc, op } => {
+                write!(f, "unknown opcode {:#06x} at {:#05x}", op, pc)
+            }
+            EmuError::StackOverflow => write!(f, "stack overflow"),
+            EmuError::StackUnderflow => write!(f, "stack underflow"),
+            EmuError::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#05x}", addr)
+            }
+            EmuError::InvalidKey { key } => write!(f, "invalid key {:#04x}", key),
+        }
+    }
+}
+
+impl Error for EmuError {}
@@ -0,0 +1,462 @@
+//! GDB Remote Serial Protocol stub, so standard debugging clients can attach over TCP.
+//!
+//! Registers are numbered V0-VF (0-15, 8 bits), I (16, 16 bits), PC (17, 16 bits),
+//! SP (18), DT (19) and ST (20), all 8 bits, little-endian on the wire.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use crate::{Debugger, EmuError, Location, StopReason, WatchKind};
+
+const TICKS_PER_FRAME: usize = 20;
+const NUM_GDB_REGS: usize = 21;
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+
+/// Target description served through `qXfer:features:read:target.xml`.
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Serves a `Debugger` to one GDB client at a time.
+#[derive(Debug)]
+pub struct GdbStub {
+    debugger: Debugger,
+    no_ack: bool,
+    frame_ticks: usize,
+}
+
+impl GdbStub {
+    pub fn new(debugger: Debugger) -> Self {
+        Self {
+            debugger,
+            no_ack: false,
+            frame_ticks: 0,
+        }
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    pub fn into_debugger(self) -> Debugger {
+        self.debugger
+    }
+
+    /// Handles packets from `stream` until the client detaches, kills the
+    /// session or disconnects.
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        self.no_ack = false;
+        stream.set_nodelay(true)?;
+        while let Some(packet) = self.read_packet(&mut stream)? {
+            let packet = String::from_utf8_lossy(&packet).into_owned();
+            let (reply, close) = match packet.as_str() {
+                "D" => (Some("OK".to_string()), true),
+                "k" => (None, true),
+                _ => (Some(self.handle(&packet, &mut stream)?), false),
+            };
+            if let Some(reply) = reply {
+                send(&mut stream, &reply)?;
+            }
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+            if close {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_packet(&mut self, stream: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            // skip acks and interrupts that arrive while already stopped
+            match read_byte(stream)? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+            loop {
+                match read_byte(stream)? {
+                    Some(b'#') => break,
+                    Some(byte) => {
+                        sum = sum.wrapping_add(byte);
+                        data.push(byte);
+                    }
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            stream.read_exact(&mut checksum)?;
+            if !self.no_ack {
+                let expected = std::str::from_utf8(&checksum)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                if expected != Some(sum) {
+                    stream.write_all(b"-")?;
+                    continue;
+                }
+                stream.write_all(b"+")?;
+            }
+            return Ok(Some(unescape(&data)));
+        }
+    }
+
+    fn handle(&mut self, packet: &str, stream: &mut TcpStream) -> io::Result<String> {
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+        let reply = match cmd {
+            "?" => "S05".to_string(),
+            "g" => (0..NUM_GDB_REGS).map(|n| hex(&self.register(n))).collect(),
+            "G" => {
+                let bytes = unhex(args).unwrap_or_default();
+                let mut pos = 0;
+                for n in 0..NUM_GDB_REGS {
+                    let size = reg_size(n);
+                    if let Some(value) = bytes.get(pos..pos + size) {
+                        // SP is read-only, the stack has to stay consistent
+                        if n != REG_SP {
+                            self.set_register(n, value);
+                        }
+                    }
+                    pos += size;
+                }
+                "OK".to_string()
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < NUM_GDB_REGS => hex(&self.register(n)),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, value)| {
+                    Some((usize::from_str_radix(n, 16).ok()?, unhex(value)?))
+                });
+                match parsed {
+                    Some((n, value)) if n < NUM_GDB_REGS && n != REG_SP => {
+                        self.set_register(n, &value);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => {
+                let ram = self.debugger.emu().get_ram();
+                match parse_range(args) {
+                    Some((addr, len)) if in_ram(addr, len, ram.len()) => {
+                        hex(&ram[addr..addr + len])
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "M" => {
+                let parsed = args
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, unhex(data)?)));
+                let ram_len = self.debugger.emu().get_ram().len();
+                match parsed {
+                    Some(((addr, len), data))
+                        if data.len() == len && in_ram(addr, len, ram_len) =>
+                    {
+                        let emu = self.debugger.emu_mut();
+                        let written = data
+                            .iter()
+                            .enumerate()
+                            .all(|(offset, byte)| emu.write_ram(addr + offset, *byte).is_ok());
+                        if written { "OK" } else { "E01" }.to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "Z" | "z" => self.breakpoint(cmd == "Z", args),
+            "s" | "c" => {
+                if let Ok(addr) = u16::from_str_radix(args, 16) {
+                    self.debugger.emu_mut().set_pc(addr);
+                }
+                self.resume(stream, cmd == "s")?
+            }
+            "H" => "OK".to_string(),
+            _ => self.query(packet),
+        };
+        Ok(reply)
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, len) = match parse_range(range) {
+                Some(range) => range,
+                None => return "E01".to_string(),
+            };
+            let xml = TARGET_XML.as_bytes();
+            let start = offset.min(xml.len());
+            let end = (offset + len).min(xml.len());
+            let prefix = if end == xml.len() { "l" } else { "m" };
+            return format!(
+                "{}{}",
+                prefix,
+                escape(&String::from_utf8_lossy(&xml[start..end]))
+            );
+        }
+        match packet {
+            "QStartNoAckMode" => "OK",
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        }
+        .to_string()
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let (kind, addr, len) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(kind), Some(addr), Some(len)) => (
+                kind,
+                usize::from_str_radix(addr, 16),
+                usize::from_str_radix(len, 16),
+            ),
+            _ => return "E01".to_string(),
+        };
+        let (addr, len) = match (addr, len) {
+            (Ok(addr), Ok(len)) if addr <= 0xFFFF => (addr, len),
+            _ => return "E01".to_string(),
+        };
+        let watch = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.debugger.add_breakpoint(addr as u16);
+                } else {
+                    self.debugger.remove_breakpoint(addr as u16);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::ReadWrite,
+            _ => return String::new(),
+        };
+        for offset in 0..len.max(1) {
+            let location = Location::Ram((addr + offset) as u16);
+            if insert {
+                self.debugger.add_watchpoint(location, watch);
+            } else {
+                self.debugger.remove_watchpoint(location);
+            }
+        }
+        "OK".to_string()
+    }
+
+    fn resume(&mut self, stream: &mut TcpStream, step: bool) -> io::Result<String> {
+        if step {
+            self.debugger.step_into();
+        } else {
+            self.debugger.resume();
+        }
+        loop {
+            if let Some(reason) = self.debugger.run(1) {
+                return Ok(self.stop_reply(reason));
+            }
+            self.frame_ticks += 1;
+            if self.frame_ticks == TICKS_PER_FRAME {
+                self.frame_ticks = 0;
+                self.debugger.emu_mut().tick_timers();
+                if interrupted(stream)? {
+                    return Ok("S02".to_string());
+                }
+            }
+        }
+    }
+
+    fn stop_reply(&self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Watchpoint {
+                location: Location::Ram(addr),
+                ..
+            } => {
+                let kind = self
+                    .debugger
+                    .watchpoints()
+                    .iter()
+                    .find(|(loc, _)| *loc == Location::Ram(addr))
+                    .map(|(_, kind)| *kind);
+                let name = match kind {
+                    Some(WatchKind::Read) => "rwatch",
+                    Some(WatchKind::ReadWrite) => "awatch",
+                    _ => "watch",
+                };
+                format!("T05{}:{:x};", name, addr)
+            }
+            StopReason::Fault(EmuError::UnknownOpcode { .. }) => "S04".to_string(),
+            StopReason::Fault(_) => "S0b".to_string(),
+            StopReason::Exited => "W00".to_string(),
+            _ => "S05".to_string(),
+        }
+    }
+
+    fn register(&self, n: usize) -> Vec<u8> {
+        let emu = self.debugger.emu();
+        match n {
+            REG_I => emu.get_i_reg().to_le_bytes().to_vec(),
+            REG_PC => emu.get_pc().to_le_bytes().to_vec(),
+            REG_SP => vec![emu.get_sp() as u8],
+            REG_DT => vec![emu.get_dt()],
+            REG_ST => vec![emu.get_st()],
+            x => vec![emu.get_v_reg()[x]],
+        }
+    }
+
+    fn set_register(&mut self, n: usize, value: &[u8]) {
+        let word = match value {
+            [lo, hi, ..] => u16::from_le_bytes([*lo, *hi]),
+            [lo] => *lo as u16,
+            [] => return,
+        };
+        let emu = self.debugger.emu_mut();
+        match n {
+            REG_I => emu.set_i_reg(word),
+            REG_PC => emu.set_pc(word),
+            REG_SP => {}
+            REG_DT => emu.set_dt(word as u8),
+            REG_ST => emu.set_st(word as u8),
+            x => emu.set_v_reg(x, word as u8),
+        }
+    }
+}
+
+fn reg_size(n: usize) -> usize {
+    match n {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+fn read_byte(stream: &mut TcpStream) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+// checks for a Ctrl-C (0x03) from the client without blocking
+fn interrupted(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0];
+    let result = stream.read(&mut byte);
+    stream.set_nonblocking(false)?;
+    match result {
+        Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(_) => Ok(byte[0] == 0x03),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn send(stream: &mut TcpStream, reply: &str) -> io::Result<()> {
+    let sum = reply.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    write!(stream, "${}#{:02x}", reply, sum)?;
+    stream.flush()
+}
+
+fn escape(data: &str) -> String {
+    let mut out = String::with_capacity(data.len());
+    for c in data.chars() {
+        if matches!(c, '#' | '$' | '}' | '*') {
+            out.push('}');
+            out.push((c as u8 ^ 0x20) as char);
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'}' => out.extend(bytes.next().map(|b| b ^ 0x20)),
+            _ => out.push(*byte),
+        }
+    }
+    out
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(text.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+// Whether `len` bytes from `addr` fit in RAM, without overflowing on huge
+// addresses sent by the client.
+fn in_ram(addr: usize, len: usize, ram_len: usize) -> bool {
+    addr.checked_add(len).is_some_and(|end| end <= ram_len)
+}
+
+// "addr,len" in hex
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((
+        usize::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(escape("a#b}"), "a}\x03b}]");
+        assert_eq!(unescape(escape("a#b}$*").as_bytes()), b"a#b}$*");
+        assert_eq!(unhex("00ff10"), Some(vec![0x00, 0xFF, 0x10]));
+        assert_eq!(unhex("0"), None);
+    }
+}
@@ -0,0 +1,181 @@
+//! Monochrome screenshots as plain PBM (easy to diff) or PNG (easy to view).
+
+use crate::state::crc32;
+use crate::Emu;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+// largest payload of a stored (uncompressed) deflate block
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+/// A 1-bit image of the display; a pixel is lit if it is set on any plane.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<bool>,
+}
+
+impl Frame {
+    pub fn capture(emu: &Emu) -> Self {
+        let (width, height) = emu.get_display_size();
+        let second_plane = emu.get_plane(1);
+        let pixels = emu
+            .get_display()
+            .iter()
+            .zip(second_plane)
+            .map(|(a, b)| *a || *b)
+            .collect();
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Plain `P1` PBM with lit pixels as 1, one text row per display row.
+    pub fn to_pbm(&self) -> String {
+        let mut out = format!("P1\n{} {}\n", self.width, self.height);
+        for row in self.pixels.chunks(self.width) {
+            out.extend(row.iter().map(|p| if *p { '1' } else { '0' }));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Reads a plain `P1` PBM, as written by `to_pbm`.
+    pub fn from_pbm(text: &str) -> Option<Self> {
+        // comments run from '#' to the end of the line
+        let mut tokens = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(|line| line.split_whitespace());
+        if tokens.next()? != "P1" {
+            return None;
+        }
+        let width: usize = tokens.next()?.parse().ok()?;
+        let height: usize = tokens.next()?.parse().ok()?;
+        let mut pixels = Vec::with_capacity(width * height);
+        for token in tokens {
+            for c in token.chars() {
+                match c {
+                    '0' => pixels.push(false),
+                    '1' => pixels.push(true),
+                    _ => return None,
+                }
+            }
+        }
+        if pixels.len() != width * height {
+            return None;
+        }
+        Some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// 1-bit grayscale PNG with lit pixels white, matching the desktop window.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(13);
+        header.extend((self.width as u32).to_be_bytes());
+        header.extend((self.height as u32).to_be_bytes());
+        // bit depth 1, grayscale, deflate, no filter, no interlace
+        header.extend([1, 0, 0, 0, 0]);
+
+        let mut raw = Vec::new();
+        for row in self.pixels.chunks(self.width) {
+            raw.push(0);
+            for byte in row.chunks(8) {
+                let bits = byte
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (bit, p)| acc | (*p as u8) << (7 - bit));
+                raw.push(bits);
+            }
+        }
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(tag);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+// zlib stream made of uncompressed deflate blocks; screens are tiny, so size doesn't matter
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard() -> Frame {
+        Frame {
+            width: 10,
+            height: 3,
+            pixels: (0..30).map(|i| i % 2 == 0).collect(),
+        }
+    }
+
+    #[test]
+    fn test_pbm_round_trip() {
+        let frame = checkerboard();
+        let pbm = frame.to_pbm();
+        assert!(pbm.starts_with("P1\n10 3\n1010101010\n"));
+        assert_eq!(Frame::from_pbm(&pbm), Some(frame));
+        assert_eq!(
+            Frame::from_pbm("P1\n# comment\n2 1\n1 0\n").unwrap().pixels,
+            [true, false]
+        );
+        assert_eq!(Frame::from_pbm("P1\n2 2\n10\n"), None);
+    }
+
+    #[test]
+    fn test_png_layout() {
+        let png = checkerboard().to_png();
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 10, 0, 0, 0, 3]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        // IDAT holds one stored block of 3 rows, each a filter byte and two bytes of pixels
+        let idat = &png[33 + 8..];
+        assert_eq!(&idat[..2], &[0x78, 0x01]);
+        assert_eq!(&idat[2..7], &[1, 9, 0, !9u8, 0xFF]);
+        assert_eq!(&idat[7..10], &[0, 0b1010_1010, 0b1000_0000]);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
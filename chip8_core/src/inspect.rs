@@ -0,0 +1,37 @@
+use crate::{Platform, Quirks, NUM_KEYS, NUM_REGS, STACK_SIZE};
+
+/// Progress of an FX0A waiting for input. The machine runs no instructions
+/// until it completes, though timers keep counting down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyWait {
+    /// Waiting for a key to go down; it will be stored in VX.
+    Press { x: u8 },
+    /// `key` went down and is taken once it is released.
+    Release { x: u8, key: u8 },
+}
+
+/// A borrowed snapshot of everything an `Emu` holds, for debuggers and overlays.
+#[derive(Debug, Clone, Copy)]
+pub struct MachineState<'a> {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub pc: u16,
+    pub i_reg: u16,
+    pub sp: u16,
+    pub dt: u8,
+    pub st: u8,
+    pub v_reg: &'a [u8; NUM_REGS],
+    pub stack: &'a [u16; STACK_SIZE],
+    pub ram: &'a [u8],
+    pub keys: &'a [bool; NUM_KEYS],
+    pub key_wait: Option<KeyWait>,
+    pub display: &'a [bool],
+    pub display_size: (usize, usize),
+}
+
+impl MachineState<'_> {
+    /// Return addresses currently on the stack, oldest first.
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+}
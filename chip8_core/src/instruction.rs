@@ -0,0 +1,343 @@
+use std::fmt;
+
+/// A decoded opcode. Register operands are register numbers (0x0 - 0xF).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0000
+    Nop,
+    /// 0NNN, machine code routine on the original hardware
+    Sys(u16),
+    /// 00CN
+    ScrollDown(u8),
+    /// 00DN
+    ScrollUp(u8),
+    /// 00E0
+    Cls,
+    /// 00EE
+    Ret,
+    /// 00FB
+    ScrollRight,
+    /// 00FC
+    ScrollLeft,
+    /// 00FD
+    Exit,
+    /// 00FE
+    Lores,
+    /// 00FF
+    Hires,
+    /// 1NNN
+    Jp(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SeByte(u8, u8),
+    /// 4XNN
+    SneByte(u8, u8),
+    /// 5XY0
+    SeReg(u8, u8),
+    /// 5XY2
+    SaveRange(u8, u8),
+    /// 5XY3
+    LoadRange(u8, u8),
+    /// 6XNN
+    LdByte(u8, u8),
+    /// 7XNN
+    AddByte(u8, u8),
+    /// 8XY0
+    LdReg(u8, u8),
+    /// 8XY1
+    Or(u8, u8),
+    /// 8XY2
+    And(u8, u8),
+    /// 8XY3
+    Xor(u8, u8),
+    /// 8XY4
+    AddReg(u8, u8),
+    /// 8XY5
+    Sub(u8, u8),
+    /// 8XY6
+    Shr(u8, u8),
+    /// 8XY7
+    Subn(u8, u8),
+    /// 8XYE
+    Shl(u8, u8),
+    /// 9XY0
+    SneReg(u8, u8),
+    /// ANNN
+    LdI(u16),
+    /// BNNN
+    JpV0(u16),
+    /// CXNN
+    Rnd(u8, u8),
+    /// DXYN
+    Drw(u8, u8, u8),
+    /// EX9E
+    Skp(u8),
+    /// EXA1
+    Sknp(u8),
+    /// F000 NNNN, the address is the following word
+    LdILong,
+    /// FN01
+    Plane(u8),
+    /// F002
+    Audio,
+    /// FX07
+    LdVxDt(u8),
+    /// FX0A
+    LdVxK(u8),
+    /// FX15
+    LdDtVx(u8),
+    /// FX18
+    LdStVx(u8),
+    /// FX1E
+    AddI(u8),
+    /// FX29
+    LdF(u8),
+    /// FX30
+    LdHf(u8),
+    /// FX33
+    LdB(u8),
+    /// FX3A
+    Pitch(u8),
+    /// FX55
+    LdIVx(u8),
+    /// FX65
+    LdVxI(u8),
+    /// FX75
+    LdRVx(u8),
+    /// FX85
+    LdVxR(u8),
+    Unknown(u16),
+}
+
+/// Decodes one opcode word. Extension opcodes are decoded regardless of platform.
+pub fn decode(op: u16) -> Instruction {
+    let digit1 = (op & 0xF000) >> 12;
+    let digit2 = (op & 0x0F00) >> 8;
+    let digit3 = (op & 0x00F0) >> 4;
+    let digit4 = op & 0x000F;
+    let x = digit2 as u8;
+    let y = digit3 as u8;
+    let n = digit4 as u8;
+    let nn = (op & 0xFF) as u8;
+    let nnn = op & 0xFFF;
+
+    match (digit1, digit2, digit3, digit4) {
+        (0, 0, 0, 0) => Instruction::Nop,
+        (0, 0, 0xC, _) => Instruction::ScrollDown(n),
+        (0, 0, 0xD, _) => Instruction::ScrollUp(n),
+        (0, 0, 0xE, 0) => Instruction::Cls,
+        (0, 0, 0xE, 0xE) => Instruction::Ret,
+        (0, 0, 0xF, 0xB) => Instruction::ScrollRight,
+        (0, 0, 0xF, 0xC) => Instruction::ScrollLeft,
+        (0, 0, 0xF, 0xD) => Instruction::Exit,
+        (0, 0, 0xF, 0xE) => Instruction::Lores,
+        (0, 0, 0xF, 0xF) => Instruction::Hires,
+        (0, _, _, _) => Instruction::Sys(nnn),
+        (1, _, _, _) => Instruction::Jp(nnn),
+        (2, _, _, _) => Instruction::Call(nnn),
+        (3, _, _, _) => Instruction::SeByte(x, nn),
+        (4, _, _, _) => Instruction::SneByte(x, nn),
+        (5, _, _, 0) => Instruction::SeReg(x, y),
+        (5, _, _, 2) => Instruction::SaveRange(x, y),
+        (5, _, _, 3) => Instruction::LoadRange(x, y),
+        (6, _, _, _) => Instruction::LdByte(x, nn),
+        (7, _, _, _) => Instruction::AddByte(x, nn),
+        (8, _, _, 0) => Instruction::LdReg(x, y),
+        (8, _, _, 1) => Instruction::Or(x, y),
+        (8, _, _, 2) => Instruction::And(x, y),
+        (8, _, _, 3) => Instruction::Xor(x, y),
+        (8, _, _, 4) => Instruction::AddReg(x, y),
+        (8, _, _, 5) => Instruction::Sub(x, y),
+        (8, _, _, 6) => Instruction::Shr(x, y),
+        (8, _, _, 7) => Instruction::Subn(x, y),
+        (8, _, _, 0xE) => Instruction::Shl(x, y),
+        (9, _, _, 0) => Instruction::SneReg(x, y),
+        (0xA, _, _, _) => Instruction::LdI(nnn),
+        (0xB, _, _, _) => Instruction::JpV0(nnn),
+        (0xC, _, _, _) => Instruction::Rnd(x, nn),
+        (0xD, _, _, _) => Instruction::Drw(x, y, n),
+        (0xE, _, 9, 0xE) => Instruction::Skp(x),
+        (0xE, _, 0xA, 1) => Instruction::Sknp(x),
+        (0xF, 0, 0, 0) => Instruction::LdILong,
+        (0xF, _, 0, 1) => Instruction::Plane(x),
+        (0xF, 0, 0, 2) => Instruction::Audio,
+        (0xF, _, 0, 7) => Instruction::LdVxDt(x),
+        (0xF, _, 0, 0xA) => Instruction::LdVxK(x),
+        (0xF, _, 1, 5) => Instruction::LdDtVx(x),
+        (0xF, _, 1, 8) => Instruction::LdStVx(x),
+        (0xF, _, 1, 0xE) => Instruction::AddI(x),
+        (0xF, _, 2, 9) => Instruction::LdF(x),
+        (0xF, _, 3, 0) => Instruction::LdHf(x),
+        (0xF, _, 3, 3) => Instruction::LdB(x),
+        (0xF, _, 3, 0xA) => Instruction::Pitch(x),
+        (0xF, _, 5, 5) => Instruction::LdIVx(x),
+        (0xF, _, 6, 5) => Instruction::LdVxI(x),
+        (0xF, _, 7, 5) => Instruction::LdRVx(x),
+        (0xF, _, 8, 5) => Instruction::LdVxR(x),
+        (_, _, _, _) => Instruction::Unknown(op),
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Nop => write!(f, "NOP"),
+            Instruction::Sys(nnn) => write!(f, "SYS {:#05X}", nnn),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Lores => write!(f, "LOW"),
+            Instruction::Hires => write!(f, "HIGH"),
+            Instruction::Jp(nnn) => write!(f, "JP {:#05X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            Instruction::SeByte(x, nn) => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Instruction::SneByte(x, nn) => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::LdByte(x, nn) => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Instruction::AddByte(x, nn) => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Instruction::LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            Instruction::JpV0(nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            Instruction::Rnd(x, nn) => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LdILong => write!(f, "LD I, LONG"),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdF(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHf(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdB(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LdVxR(x) => write!(f, "LD V{:X}, R", x),
+            Instruction::Unknown(op) => write!(f, "DW {:#06X}", op),
+        }
+    }
+}
+
+/// One line of a disassembly listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disassembly {
+    pub addr: u16,
+    pub op: u16,
+    pub instruction: Instruction,
+    /// The address word following an XO-CHIP `F000`.
+    pub operand: Option<u16>,
+    /// Bytes covered by this line: 1 for a trailing odd byte, 2, or 4 with an operand.
+    pub size: u16,
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.size, self.operand) {
+            (1, _) => write!(f, "{:04X}: {:02X}         DB {:#04X}", self.addr, self.op, self.op),
+            (_, Some(nnnn)) => write!(
+                f,
+                "{:04X}: {:04X} {:04X}  LD I, LONG {:#06X}",
+                self.addr, self.op, nnnn, nnnn
+            ),
+            _ => write!(f, "{:04X}: {:04X}       {}", self.addr, self.op, self.instruction),
+        }
+    }
+}
+
+/// Linear sweep over `rom`, which is assumed to be loaded at `base_addr`.
+pub fn disassemble(rom: &[u8], base_addr: u16) -> Vec<Disassembly> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = base_addr.wrapping_add(offset as u16);
+        if offset + 1 == rom.len() {
+            let byte = rom[offset] as u16;
+            lines.push(Disassembly {
+                addr,
+                op: byte,
+                instruction: Instruction::Unknown(byte),
+                operand: None,
+                size: 1,
+            });
+            break;
+        }
+        let op = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+        let instruction = decode(op);
+        let operand = match instruction {
+            Instruction::LdILong if offset + 3 < rom.len() => {
+                Some((rom[offset + 2] as u16) << 8 | rom[offset + 3] as u16)
+            }
+            _ => None,
+        };
+        let size = if operand.is_some() { 4 } else { 2 };
+        lines.push(Disassembly {
+            addr,
+            op,
+            instruction,
+            operand,
+            size,
+        });
+        offset += size as usize;
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(0x00E0), Instruction::Cls);
+        assert_eq!(decode(0x0123), Instruction::Sys(0x123));
+        assert_eq!(decode(0x631F), Instruction::LdByte(3, 0x1F));
+        assert_eq!(decode(0xD015), Instruction::Drw(0, 1, 5));
+        assert_eq!(decode(0x8AB6), Instruction::Shr(0xA, 0xB));
+        assert_eq!(decode(0xF201), Instruction::Plane(2));
+        assert_eq!(decode(0x5121), Instruction::Unknown(0x5121));
+        assert_eq!(decode(0xF102), Instruction::Unknown(0xF102));
+    }
+
+    #[test]
+    fn test_mnemonics() {
+        assert_eq!(decode(0x631F).to_string(), "LD V3, 0x1F");
+        assert_eq!(decode(0xD015).to_string(), "DRW V0, V1, 5");
+        assert_eq!(decode(0x1200).to_string(), "JP 0x200");
+        assert_eq!(decode(0xFA65).to_string(), "LD VA, [I]");
+        assert_eq!(decode(0xFFFF).to_string(), "DW 0xFFFF");
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let rom = [0x00, 0xE0, 0xF0, 0x00, 0x12, 0x34, 0xA2, 0x0A, 0x42];
+        let lines = disassemble(&rom, 0x200);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1].operand, Some(0x1234));
+        assert_eq!(lines[2].addr, 0x206);
+        assert_eq!(lines[2].instruction, Instruction::LdI(0x20A));
+        assert_eq!(lines[0].to_string(), "0200: 00E0       CLS");
+        assert_eq!(lines[1].to_string(), "0202: F000 1234  LD I, LONG 0x1234");
+        assert_eq!(lines[3].to_string(), "0208: 42         DB 0x42");
+    }
+}
@@ -1,12 +1,53 @@
-use rand::Rng;
+use audio::{DEFAULT_PITCH, PATTERN_SIZE};
+use rng::Rng;
+use std::collections::VecDeque;
+use std::io;
+use trace::{Registers, Tracer};
+
+pub mod asm;
+mod audio;
+mod debugger;
+mod dirty;
+mod error;
+pub mod gdb;
+pub mod image;
+mod inspect;
+mod instruction;
+mod movie;
+pub mod octo;
+mod platform;
+mod quirks;
+mod rewind;
+mod rng;
+mod scheduler;
+mod state;
+mod trace;
+
+pub use audio::{AudioGenerator, AudioState};
+pub use debugger::{Access, Debugger, Location, StopReason, WatchKind};
+pub use dirty::{DirtyRegion, Rect};
+pub use error::EmuError;
+pub use inspect::{KeyWait, MachineState};
+pub use instruction::{decode, disassemble, Disassembly, Instruction};
+pub use movie::{KeyEvent, Movie, MovieError};
+pub use platform::Platform;
+pub use quirks::Quirks;
+pub use rewind::RewindBuffer;
+pub use rng::random_seed;
+pub use scheduler::Scheduler;
+pub use state::StateError;
+
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
 
 const START_ADDR: u16 = 0x200;
-const RAM_SIZE: usize = 4096;
 const NUM_REGS: usize = 16;
 const STACK_SIZE: usize = 16;
 const NUM_KEYS: usize = 16;
+const NUM_RPL_FLAGS: usize = 16;
+const NUM_PLANES: usize = 2;
 const FONTSET_SIZE: usize = 80;
 const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -26,219 +67,642 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
+const BIG_FONTSET_ADDR: usize = FONTSET_SIZE;
+const BIG_FONTSET_SIZE: usize = 160;
+const BIG_FONTSET: [u8; BIG_FONTSET_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
 
 #[derive(Debug)]
 pub struct Emu {
     pc: u16,
-    ram: [u8; RAM_SIZE],
-    screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
+    ram: Vec<u8>,
+    screen: [[bool; HIRES_WIDTH * HIRES_HEIGHT]; NUM_PLANES],
+    hires: bool,
+    planes: u8,
     v_reg: [u8; NUM_REGS],
     i_reg: u16,
     sp: u16,
     stack: [u16; STACK_SIZE],
     keys: [bool; NUM_KEYS],
+    key_wait: Option<KeyWait>,
     dt: u8,
     st: u8,
+    platform: Platform,
+    quirks: Quirks,
+    vblank_wait: bool,
+    exited: bool,
+    rpl: [u8; NUM_RPL_FLAGS],
+    pattern: Option<[u8; PATTERN_SIZE]>,
+    pitch: u8,
+    rng: Rng,
+    dirty: DirtyRegion,
+    tracer: Option<Tracer>,
+    frames: u64,
+    ticks: u64,
+    recording: Option<Movie>,
+    playback: VecDeque<KeyEvent>,
+}
+
+impl Default for Emu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Emu {
     pub fn new() -> Self {
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        Self::with_platform(Platform::Chip8, quirks)
+    }
+
+    /// Seeds CXNN so runs with the same inputs produce identical results.
+    pub fn with_seed(seed: u64) -> Self {
+        let mut emu = Self::new();
+        emu.reseed(seed);
+        emu
+    }
+
+    pub fn with_platform(platform: Platform, quirks: Quirks) -> Self {
         let mut new_emu = Self {
             pc: START_ADDR,
-            ram: [0; RAM_SIZE],
-            screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            ram: vec![0; platform.ram_size()],
+            screen: [[false; HIRES_WIDTH * HIRES_HEIGHT]; NUM_PLANES],
+            hires: false,
+            planes: 1,
             v_reg: [0; NUM_REGS],
             i_reg: 0,
             sp: 0,
             stack: [0; STACK_SIZE],
             keys: [false; NUM_KEYS],
+            key_wait: None,
             dt: 0,
             st: 0,
+            platform,
+            quirks,
+            vblank_wait: false,
+            exited: false,
+            rpl: [0; NUM_RPL_FLAGS],
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+            rng: Rng::new(random_seed()),
+            dirty: DirtyRegion::default(),
+            tracer: None,
+            frames: 0,
+            ticks: 0,
+            recording: None,
+            playback: VecDeque::new(),
         };
-        new_emu.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        new_emu.dirty.mark_all(new_emu.get_display_size());
+        new_emu.load_fonts();
         new_emu
     }
 
+    /// Restarts the machine; this also ends any movie recording or playback.
     pub fn reset(&mut self) {
         self.pc = START_ADDR;
-        self.ram = [0; RAM_SIZE];
-        self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        self.ram.fill(0);
+        self.screen = [[false; HIRES_WIDTH * HIRES_HEIGHT]; NUM_PLANES];
+        self.hires = false;
+        self.planes = 1;
         self.v_reg = [0; NUM_REGS];
         self.i_reg = 0;
         self.sp = 0;
         self.stack = [0; STACK_SIZE];
         self.keys = [false; NUM_KEYS];
+        self.key_wait = None;
         self.dt = 0;
         self.st = 0;
+        self.vblank_wait = false;
+        self.exited = false;
+        self.pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.dirty.mark_all(self.get_display_size());
+        self.frames = 0;
+        self.ticks = 0;
+        self.recording = None;
+        self.playback.clear();
+        self.load_fonts();
+    }
+
+    fn load_fonts(&mut self) {
         self.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        if self.platform.supports_schip() {
+            self.ram[BIG_FONTSET_ADDR..BIG_FONTSET_ADDR + BIG_FONTSET_SIZE]
+                .copy_from_slice(&BIG_FONTSET);
+        }
     }
 
-    fn push(&mut self, val: u16) {
+    fn push(&mut self, val: u16) -> Result<(), EmuError> {
+        if self.sp as usize >= STACK_SIZE {
+            return Err(EmuError::StackOverflow);
+        }
         self.stack[self.sp as usize] = val;
         self.sp += 1;
+        Ok(())
     }
 
-    fn pop(&mut self) -> u16 {
+    fn pop(&mut self) -> Result<u16, EmuError> {
+        if self.sp == 0 {
+            return Err(EmuError::StackUnderflow);
+        }
         self.sp -= 1;
-        self.stack[self.sp as usize]
+        Ok(self.stack[self.sp as usize])
+    }
+
+    fn read(&self, addr: usize) -> Result<u8, EmuError> {
+        self.ram
+            .get(addr)
+            .copied()
+            .ok_or(EmuError::MemoryOutOfBounds { addr })
+    }
+
+    fn write(&mut self, addr: usize, val: u8) -> Result<(), EmuError> {
+        let byte = self
+            .ram
+            .get_mut(addr)
+            .ok_or(EmuError::MemoryOutOfBounds { addr })?;
+        *byte = val;
+        Ok(())
+    }
+
+    fn key(&self, key: u8) -> Result<bool, EmuError> {
+        self.keys
+            .get(key as usize)
+            .copied()
+            .ok_or(EmuError::InvalidKey { key: key as usize })
+    }
+
+    pub fn tick(&mut self) -> Result<(), EmuError> {
+        self.play_due_keys();
+        self.ticks += 1;
+        if self.exited || self.key_wait.is_some() {
+            return Ok(());
+        }
+        let pc = self.pc;
+        let op = self.fetch()?;
+        if self.tracer.is_none() {
+            return self.execute(op);
+        }
+        let before = Registers::capture(self);
+        let result = self.execute(op);
+        let after = Registers::capture(self);
+        if let Some(tracer) = &mut self.tracer {
+            tracer.log(pc, op, &before, result.as_ref().map(|_| &after));
+        }
+        result
     }
 
-    pub fn tick(&mut self) {
-        let op = self.fetch();
-        self.execute(op);
+    /// Logs every instruction `tick` executes to `out`, one line each with
+    /// the registers before and after. Replaces any trace already running.
+    pub fn trace_to(&mut self, out: impl io::Write + Send + 'static) {
+        self.tracer = Some(Tracer::new(Box::new(out)));
+    }
+
+    /// Stops tracing and flushes, returning the first write error if any.
+    pub fn stop_trace(&mut self) -> io::Result<()> {
+        match self.tracer.take() {
+            Some(tracer) => tracer.finish(),
+            None => Ok(()),
+        }
     }
 
     pub fn get_display(&self) -> &[bool] {
-        &self.screen
+        self.get_plane(0)
+    }
+
+    /// One XO-CHIP bitplane; plane 0 is what `get_display` returns.
+    pub fn get_plane(&self, plane: usize) -> &[bool] {
+        let (width, height) = self.get_display_size();
+        &self.screen[plane][..width * height]
+    }
+
+    /// Width and height of the active resolution, in pixels.
+    pub fn get_display_size(&self) -> (usize, usize) {
+        if self.hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        }
+    }
+
+    /// What the display changes since the previous call touched, so
+    /// frontends can skip presenting unchanged frames or send only deltas.
+    pub fn take_dirty(&mut self) -> DirtyRegion {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Set once a SUPER-CHIP ROM runs 00FD; `tick` does nothing afterwards.
+    pub fn has_exited(&self) -> bool {
+        self.exited
     }
 
-    pub fn keypress(&mut self, idx: usize, pressed: bool) {
-        self.keys[idx] = pressed;
+    /// Presses or releases key `idx` (0 to F) on the keypad.
+    pub fn keypress(&mut self, idx: usize, pressed: bool) -> Result<(), EmuError> {
+        if idx >= NUM_KEYS {
+            return Err(EmuError::InvalidKey { key: idx });
+        }
+        self.press_key(idx, pressed);
+        Ok(())
+    }
+
+    fn press_key(&mut self, idx: usize, pressed: bool) {
+        if let Some(movie) = &mut self.recording {
+            movie.events.push(KeyEvent {
+                frame: self.frames,
+                tick: self.ticks,
+                key: idx as u8,
+                pressed,
+            });
+        }
+        let was_pressed = std::mem::replace(&mut self.keys[idx], pressed);
+        self.key_wait = match self.key_wait {
+            // keys already down when FX0A started don't count, only fresh presses
+            Some(KeyWait::Press { x }) if pressed && !was_pressed => {
+                if self.quirks.key_wait_held {
+                    self.v_reg[x as usize] = idx as u8;
+                    None
+                } else {
+                    Some(KeyWait::Release { x, key: idx as u8 })
+                }
+            }
+            Some(KeyWait::Release { x, key }) if !pressed && key as usize == idx => {
+                self.v_reg[x as usize] = key;
+                None
+            }
+            wait => wait,
+        };
     }
 
-    pub fn load(&mut self, data: &[u8]) {
+    /// The FX0A currently blocking execution, if any.
+    pub fn key_wait(&self) -> Option<KeyWait> {
+        self.key_wait
+    }
+
+    /// Copies a ROM to 0x200, failing without touching memory if it doesn't fit.
+    pub fn load(&mut self, data: &[u8]) -> Result<(), EmuError> {
         let start = START_ADDR as usize;
-        let end = (START_ADDR as usize) + data.len();
-        self.ram[start..end].copy_from_slice(data);
+        let end = start + data.len();
+        let ram_size = self.ram.len();
+        self.ram
+            .get_mut(start..end)
+            .ok_or(EmuError::MemoryOutOfBounds { addr: ram_size })?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
     }
 
     pub fn tick_timers(&mut self) {
+        self.play_due_keys();
+        self.frames += 1;
+        self.vblank_wait = false;
         if self.dt > 0 {
             self.dt -= 1;
         }
 
         if self.st > 0 {
-            if self.st == 1 {
-                // to implement
-            }
             self.st -= 1;
         }
     }
 
-    fn fetch(&mut self) -> u16 {
-        let higher_byte = self.ram[self.pc as usize] as u16;
-        let lower_byte = self.ram[(self.pc + 1) as usize] as u16;
+    /// Calls to `tick_timers` since construction or `reset`, i.e. frames run.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Calls to `tick` since construction or `reset`, including ones that
+    /// did nothing because the machine was waiting for a key or had exited.
+    pub fn tick_count(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Starts logging every `keypress` into `movie`, stamped with the
+    /// current frame and tick counts. `movie` should describe how this
+    /// machine was set up, normally by being made with `Movie::new` right
+    /// after loading the ROM.
+    pub fn start_recording(&mut self, movie: Movie) {
+        self.recording = Some(movie);
+    }
+
+    /// Stops recording and hands back the movie with its events.
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        self.recording.take()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Queues the movie's key events to be replayed by `tick` and
+    /// `tick_timers` at the counts they were recorded at. Frontends should
+    /// stop passing their own input in while `is_playing`. Fails without
+    /// queueing anything if an event presses a key past F.
+    pub fn start_playback(&mut self, movie: &Movie) -> Result<(), EmuError> {
+        if let Some(event) = movie.events.iter().find(|e| e.key as usize >= NUM_KEYS) {
+            return Err(EmuError::InvalidKey {
+                key: event.key as usize,
+            });
+        }
+        self.playback = movie.events.iter().copied().collect();
+        Ok(())
+    }
+
+    /// True until the last queued movie event has been replayed.
+    pub fn is_playing(&self) -> bool {
+        !self.playback.is_empty()
+    }
+
+    fn play_due_keys(&mut self) {
+        let (frames, ticks) = (self.frames, self.ticks);
+        while let Some(event) = self
+            .playback
+            .pop_front_if(|e| e.frame <= frames && e.tick <= ticks)
+        {
+            self.press_key(event.key as usize, event.pressed);
+        }
+    }
+
+    /// True while the sound timer is running and the buzzer should sound.
+    pub fn is_beeping(&self) -> bool {
+        self.st > 0
+    }
+
+    pub fn audio_state(&self) -> AudioState {
+        AudioState {
+            beeping: self.is_beeping(),
+            pattern: self.pattern,
+            pitch: self.pitch,
+        }
+    }
+
+    fn fetch(&mut self) -> Result<u16, EmuError> {
+        let higher_byte = self.read(self.pc as usize)? as u16;
+        let lower_byte = self.read(self.pc as usize + 1)? as u16;
         let op = (higher_byte << 8) | lower_byte;
-        self.pc += 2;
-        op
+        self.pc = self.pc.wrapping_add(2);
+        Ok(op)
     }
 
-    fn execute(&mut self, op: u16) {
-        let digit1 = (op & 0xF000) >> 12;
-        let digit2 = (op & 0x0F00) >> 8;
-        let digit3 = (op & 0x00F0) >> 4;
-        let digit4 = op & 0x000F;
+    fn skip(&mut self) {
+        // XO-CHIP skips have to hop over both words of F000 NNNN
+        let long = self.platform.supports_xo()
+            && self.read(self.pc as usize) == Ok(0xF0)
+            && self.read(self.pc as usize + 1) == Ok(0x00);
+        let len = if long { 4 } else { 2 };
+        self.pc = self.pc.wrapping_add(len);
+    }
 
-        match (digit1, digit2, digit3, digit4) {
+    fn execute(&mut self, op: u16) -> Result<(), EmuError> {
+        let schip = self.platform.supports_schip();
+        let xo = self.platform.supports_xo();
+
+        match decode(op) {
             // NOP
-            (0, 0, 0, 0) => return,
+            Instruction::Nop => {}
+            // SCROLL DOWN N
+            Instruction::ScrollDown(n) if schip => self.scroll_down(n as usize),
+            // SCROLL UP N
+            Instruction::ScrollUp(n) if xo => self.scroll_up(n as usize),
             // CLS
-            (0, 0, 0xE, 0) => self.clear_screen(), // RET
-            (0, 0, 0xE, 0xE) => self.ret(),
+            Instruction::Cls => self.clear_screen(),
+            // RET
+            Instruction::Ret => self.ret()?,
+            // SCROLL RIGHT
+            Instruction::ScrollRight if schip => self.scroll_right(4),
+            // SCROLL LEFT
+            Instruction::ScrollLeft if schip => self.scroll_left(4),
+            // EXIT
+            Instruction::Exit if schip => self.exited = true,
+            // LORES
+            Instruction::Lores if schip => self.set_hires(false),
+            // HIRES
+            Instruction::Hires if schip => self.set_hires(true),
             // JMP NNN
-            (1, _, _, _) => self.jmp_nnn(op),
+            Instruction::Jp(_) => self.jmp_nnn(op),
             // CALL NNN
-            (2, _, _, _) => self.call_nnn(op),
+            Instruction::Call(_) => self.call_nnn(op)?,
             // SKIP V[X] == NN
-            (3, _, _, _) => self.skip_vx_eqnn(op, digit2),
+            Instruction::SeByte(x, _) => self.skip_vx_eqnn(op, x as u16),
             // SKIP V[X] != NN
-            (4, _, _, _) => self.skip_vx_neqenn(op, digit2),
+            Instruction::SneByte(x, _) => self.skip_vx_neqenn(op, x as u16),
             // SKIP V[X] == V[Y]
-            (5, _, _, _) => self.skip_vx_eqvy(digit2, digit3),
+            Instruction::SeReg(x, y) => self.skip_vx_eqvy(x as u16, y as u16),
+            // STORE V[X] - V[Y]
+            Instruction::SaveRange(x, y) if xo => self.store_vx_vy(x as u16, y as u16)?,
+            // LOAD V[X] - V[Y]
+            Instruction::LoadRange(x, y) if xo => self.ld_vx_vy(x as u16, y as u16)?,
             // V[X] = NN
-            (6, _, _, _) => self.vx_eqnn(op, digit2),
+            Instruction::LdByte(x, _) => self.vx_eqnn(op, x as u16),
             // V[X] += NN
-            (7, _, _, _) => self.vx_plusnn(op, digit2),
+            Instruction::AddByte(x, _) => self.vx_plusnn(op, x as u16),
             // V[X] = V[Y]
-            (8, _, _, 0) => self.vx_eq_vy(digit2, digit3),
+            Instruction::LdReg(x, y) => self.vx_eq_vy(x as u16, y as u16),
             // V[X] |= V[Y]
-            (8, _, _, 1) => self.vx_or_vy(digit2, digit3),
+            Instruction::Or(x, y) => self.vx_or_vy(x as u16, y as u16),
             // V[X] &= V[Y]
-            (8, _, _, 2) => self.vx_and_vy(digit2, digit3),
+            Instruction::And(x, y) => self.vx_and_vy(x as u16, y as u16),
             // V[X] ^= V[Y]
-            (8, _, _, 3) => self.vx_xor_vy(digit2, digit3),
+            Instruction::Xor(x, y) => self.vx_xor_vy(x as u16, y as u16),
             // V[X] += V[Y]
-            (8, _, _, 4) => self.vx_plus_eqvy(digit2, digit3),
+            Instruction::AddReg(x, y) => self.vx_plus_eqvy(x as u16, y as u16),
             // V[X] -= V[Y]
-            (8, _, _, 5) => self.vx_minus_eqvy(digit2, digit3),
+            Instruction::Sub(x, y) => self.vx_minus_eqvy(x as u16, y as u16),
             // V[X] >>= 1
-            (8, _, _, 6) => self.vx_bitshiftright(digit2),
+            Instruction::Shr(x, y) => self.vx_bitshiftright(x as u16, y as u16),
             // V[X] = V[Y] - V[X]
-            (8, _, _, 7) => self.vx_eqvy_minusvx(digit2, digit3),
+            Instruction::Subn(x, y) => self.vx_eqvy_minusvx(x as u16, y as u16),
             // V[X] <<= 1
-            (8, _, _, 0xE) => self.vx_bitshiftleft(digit2),
+            Instruction::Shl(x, y) => self.vx_bitshiftleft(x as u16, y as u16),
             // SKIP V[X] != V[Y]
-            (9, _, _, 0) => self.skip_eq_vx_neqvy(digit2, digit3),
+            Instruction::SneReg(x, y) => self.skip_eq_vx_neqvy(x as u16, y as u16),
             // I = NNN
-            (0xA, _, _, _) => self.i_eq_nnn(op),
+            Instruction::LdI(_) => self.i_eq_nnn(op),
             // JMP V[0] + NNN
-            (0xB, _, _, _) => self.jmp_vzero_plusnnn(op),
+            Instruction::JpV0(_) => self.jmp_vzero_plusnnn(op),
             // V[X] = rand() & NN
-            (0xC, _, _, _) => self.vx_eqrand_and_nnn(op, digit2),
+            Instruction::Rnd(x, _) => self.vx_eqrand_and_nnn(op, x as u16),
             // DRAW
-            (0xD, _, _, _) => self.draw(digit2, digit3, digit4),
+            Instruction::Drw(x, y, n) => self.draw(x as u16, y as u16, n as u16)?,
             // SKIP KEY PRESS
-            (0xE, _, 9, 0xE) => self.skip_keypress(digit2),
+            Instruction::Skp(x) => self.skip_keypress(x as u16)?,
             // SKIP KEY RELEASE
-            (0xE, _, 0xA, 1) => self.skip_keyrelease(digit2),
+            Instruction::Sknp(x) => self.skip_keyrelease(x as u16)?,
+            // I = NNNN
+            Instruction::LdILong if xo => self.i_eq_long()?,
+            // PLANE N
+            Instruction::Plane(n) if xo => self.planes = n & 0x3,
+            // AUDIO
+            Instruction::Audio if xo => self.load_pattern()?,
             // V[X] = DT
-            (0xF, _, 0, 7) => self.vx_eq_delaytimer(digit2),
+            Instruction::LdVxDt(x) => self.vx_eq_delaytimer(x as u16),
             // WAIT KEY
-            (0xF, _, 0, 0xA) => self.wait(digit2),
+            Instruction::LdVxK(x) => self.wait(x as u16),
             // DT = V[X]
-            (0xF, _, 1, 5) => self.delaytimer_eq_vx(digit2),
+            Instruction::LdDtVx(x) => self.delaytimer_eq_vx(x as u16),
             // ST = V[X]
-            (0xF, _, 1, 8) => self.soundtimer_eq_vx(digit2),
+            Instruction::LdStVx(x) => self.soundtimer_eq_vx(x as u16),
             // I += V[X]
-            (0xF, _, 1, 0xE) => self.instruction_plus_eq_vx(digit2),
+            Instruction::AddI(x) => self.instruction_plus_eq_vx(x as u16),
             // I = FONT
-            (0xF, _, 2, 9) => self.i_eq_font(digit2),
+            Instruction::LdF(x) => self.i_eq_font(x as u16),
+            // I = BIG FONT
+            Instruction::LdHf(x) if schip => self.i_eq_bigfont(x as u16),
+            // PITCH = V[X]
+            Instruction::Pitch(x) if xo => self.pitch = self.v_reg[x as usize],
             // BCD
-            (0xF, _, 3, 3) => self.bcd(digit2),
+            Instruction::LdB(x) => self.bcd(x as u16)?,
             // STORE V[0] - V[X]
-            (0xF, _, 5, 5) => self.store_v0_vx(digit2),
+            Instruction::LdIVx(x) => self.store_v0_vx(x as u16)?,
             // LOAD V[0] - V[X]
-            (0xF, _, 6, 5) => self.ld_v0_vx(digit2),
-            (_, _, _, _) => unimplemented!("unimplemented opcode: {:#04x}", op),
+            Instruction::LdVxI(x) => self.ld_v0_vx(x as u16)?,
+            // STORE V[0] - V[X] IN RPL
+            Instruction::LdRVx(x) if schip => self.store_rpl(x as u16),
+            // LOAD V[0] - V[X] FROM RPL
+            Instruction::LdVxR(x) if schip => self.ld_rpl(x as u16),
+            _ => {
+                return Err(EmuError::UnknownOpcode {
+                    pc: self.pc.wrapping_sub(2),
+                    op,
+                })
+            }
         }
+        Ok(())
     }
 
     // functions for opcodes
     fn clear_screen(&mut self) {
-        self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for plane in self.selected_planes() {
+            self.screen[plane] = [false; HIRES_WIDTH * HIRES_HEIGHT];
+        }
+        self.dirty.mark_all(self.get_display_size());
+    }
+
+    fn selected_planes(&self) -> impl Iterator<Item = usize> {
+        let planes = self.planes;
+        (0..NUM_PLANES).filter(move |plane| planes & (1 << plane) != 0)
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.screen = [[false; HIRES_WIDTH * HIRES_HEIGHT]; NUM_PLANES];
+        self.dirty.mark_all(self.get_display_size());
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        let (width, height) = self.get_display_size();
+        for plane in self.selected_planes() {
+            let screen = &mut self.screen[plane];
+            for y in (0..height).rev() {
+                for x in 0..width {
+                    screen[x + width * y] = y >= n && screen[x + width * (y - n)];
+                }
+            }
+        }
+        self.dirty.mark_all((width, height));
+    }
+
+    fn scroll_up(&mut self, n: usize) {
+        let (width, height) = self.get_display_size();
+        for plane in self.selected_planes() {
+            let screen = &mut self.screen[plane];
+            for y in 0..height {
+                for x in 0..width {
+                    screen[x + width * y] = y + n < height && screen[x + width * (y + n)];
+                }
+            }
+        }
+        self.dirty.mark_all((width, height));
     }
-    fn ret(&mut self) {
-        let ret_addr = self.pop();
+
+    fn scroll_right(&mut self, n: usize) {
+        let (width, height) = self.get_display_size();
+        for plane in self.selected_planes() {
+            let screen = &mut self.screen[plane];
+            for y in 0..height {
+                for x in (0..width).rev() {
+                    screen[x + width * y] = x >= n && screen[x - n + width * y];
+                }
+            }
+        }
+        self.dirty.mark_all((width, height));
+    }
+
+    fn scroll_left(&mut self, n: usize) {
+        let (width, height) = self.get_display_size();
+        for plane in self.selected_planes() {
+            let screen = &mut self.screen[plane];
+            for y in 0..height {
+                for x in 0..width {
+                    screen[x + width * y] = x + n < width && screen[x + n + width * y];
+                }
+            }
+        }
+        self.dirty.mark_all((width, height));
+    }
+
+    fn ret(&mut self) -> Result<(), EmuError> {
+        let ret_addr = self.pop()?;
         self.pc = ret_addr;
+        Ok(())
     }
 
     fn jmp_nnn(&mut self, op: u16) {
         self.pc = op & 0xFFF;
     }
 
-    fn call_nnn(&mut self, op: u16) {
+    fn call_nnn(&mut self, op: u16) -> Result<(), EmuError> {
         let nnn = op & 0xFFF;
-        self.push(self.pc);
+        self.push(self.pc)?;
         self.pc = nnn;
+        Ok(())
     }
 
     fn skip_vx_eqnn(&mut self, op: u16, x: u16) {
         let nn = (op & 0xFF) as u8;
         if self.v_reg[x as usize] == nn {
-            self.pc += 2;
+            self.skip();
         }
     }
 
     fn skip_vx_neqenn(&mut self, op: u16, x: u16) {
         let nn = (op & 0xFF) as u8;
         if self.v_reg[x as usize] != nn {
-            self.pc += 2;
+            self.skip();
         }
     }
 
     fn skip_vx_eqvy(&mut self, x: u16, y: u16) {
         if self.v_reg[x as usize] == self.v_reg[y as usize] {
-            self.pc += 2;
+            self.skip();
         }
     }
 
@@ -258,14 +722,23 @@ impl Emu {
 
     fn vx_or_vy(&mut self, x: u16, y: u16) {
         self.v_reg[x as usize] |= self.v_reg[y as usize];
+        if self.quirks.vf_reset {
+            self.v_reg[0xF] = 0;
+        }
     }
 
     fn vx_and_vy(&mut self, x: u16, y: u16) {
         self.v_reg[x as usize] &= self.v_reg[y as usize];
+        if self.quirks.vf_reset {
+            self.v_reg[0xF] = 0;
+        }
     }
 
     fn vx_xor_vy(&mut self, x: u16, y: u16) {
         self.v_reg[x as usize] ^= self.v_reg[y as usize];
+        if self.quirks.vf_reset {
+            self.v_reg[0xF] = 0;
+        }
     }
     fn vx_plus_eqvy(&mut self, x: u16, y: u16) {
         let (new_vx, carry) = self.v_reg[x as usize].overflowing_add(self.v_reg[y as usize]);
@@ -281,7 +754,10 @@ impl Emu {
         self.v_reg[0xF] = new_vf;
     }
 
-    fn vx_bitshiftright(&mut self, x: u16) {
+    fn vx_bitshiftright(&mut self, x: u16, y: u16) {
+        if !self.quirks.shift {
+            self.v_reg[x as usize] = self.v_reg[y as usize];
+        }
         let lsb = self.v_reg[x as usize] & 1;
         self.v_reg[x as usize] >>= 1;
         self.v_reg[0xF] = lsb;
@@ -294,7 +770,10 @@ impl Emu {
         self.v_reg[0xF] = new_vf;
     }
 
-    fn vx_bitshiftleft(&mut self, x: u16) {
+    fn vx_bitshiftleft(&mut self, x: u16, y: u16) {
+        if !self.quirks.shift {
+            self.v_reg[x as usize] = self.v_reg[y as usize];
+        }
         let msb = (self.v_reg[x as usize] >> 7) & 1;
         self.v_reg[x as usize] <<= 1;
         self.v_reg[0xF] = msb;
@@ -302,7 +781,7 @@ impl Emu {
 
     fn skip_eq_vx_neqvy(&mut self, x: u16, y: u16) {
         if self.v_reg[x as usize] != self.v_reg[y as usize] {
-            self.pc += 2;
+            self.skip();
         }
     }
 
@@ -313,56 +792,105 @@ impl Emu {
 
     fn jmp_vzero_plusnnn(&mut self, op: u16) {
         let nnn = op & 0xFFF;
-        self.pc = (self.v_reg[0] as u16) + nnn;
+        let x = if self.quirks.jump {
+            ((op & 0x0F00) >> 8) as usize
+        } else {
+            0
+        };
+        self.pc = (self.v_reg[x] as u16) + nnn;
     }
 
     fn vx_eqrand_and_nnn(&mut self, op: u16, x: u16) {
         let nn = (op & 0xFF) as u8;
-        let rng: u8 = rand::thread_rng().gen();
+        let rng = self.rng.next_u8();
         self.v_reg[x as usize] = rng & nn;
     }
 
-    fn draw(&mut self, x: u16, y: u16, rows: u16) {
-        let x_coord = self.v_reg[x as usize] as u16;
-        let y_coord = self.v_reg[y as usize] as u16;
-        let num_rows = rows;
+    fn draw(&mut self, x: u16, y: u16, rows: u16) -> Result<(), EmuError> {
+        if self.quirks.display_wait {
+            if self.vblank_wait {
+                self.pc = self.pc.wrapping_sub(2);
+                return Ok(());
+            }
+            self.vblank_wait = true;
+        }
+        let (width, height) = self.get_display_size();
+        let x_coord = self.v_reg[x as usize] as usize % width;
+        let y_coord = self.v_reg[y as usize] as usize % height;
+        // DXY0 draws a 16x16 sprite made of two bytes per row
+        let big = rows == 0 && self.platform.supports_schip();
+        let (sprite_width, num_rows) = if big { (16, 16) } else { (8, rows as usize) };
+        let row_bytes = sprite_width / 8;
+
+        // with several XO-CHIP planes selected, each plane takes the next sprite in memory
+        let mut addr = self.i_reg as usize;
+        // SUPER-CHIP hires reports how many rows collided, XO-CHIP just a flag
+        let count_rows = self.hires && !self.platform.supports_xo();
+        let mut collided_rows = 0;
+        for plane in self.selected_planes().collect::<Vec<_>>() {
+            for y_line in 0..num_rows {
+                let pixels = if big {
+                    (self.read(addr)? as u16) << 8 | self.read(addr + 1)? as u16
+                } else {
+                    (self.read(addr)? as u16) << 8
+                };
+                addr += row_bytes;
+                let y = y_coord + y_line;
+                if self.quirks.clipping && y >= height {
+                    // SUPER-CHIP counts rows clipped off the bottom as collisions
+                    if count_rows {
+                        collided_rows += 1;
+                    }
+                    continue;
+                }
+                let y = y % height;
 
-        let mut flipped = false;
-        for y_line in 0..num_rows {
-            let addr = self.i_reg + y_line as u16;
-            let pixels = self.ram[addr as usize];
-            for x_line in 0..8 {
-                if (pixels & (0b1000_0000 >> x_line)) != 0 {
-                    let x = (x_coord + x_line) as usize % SCREEN_WIDTH;
-                    let y = (y_coord + y_line) as usize % SCREEN_HEIGHT;
+                let mut flipped = false;
+                for x_line in 0..sprite_width {
+                    if (pixels & (0x8000 >> x_line)) != 0 {
+                        let x = x_coord + x_line;
+                        if self.quirks.clipping && x >= width {
+                            continue;
+                        }
+                        let x = x % width;
 
-                    let idx = x + SCREEN_WIDTH * y;
-                    flipped |= self.screen[idx];
-                    self.screen[idx] ^= true;
+                        let idx = x + width * y;
+                        flipped |= self.screen[plane][idx];
+                        self.screen[plane][idx] ^= true;
+                        self.dirty.mark(x, y);
+                    }
+                }
+                if flipped {
+                    collided_rows += 1;
                 }
             }
         }
-        if flipped {
+        if count_rows {
+            self.v_reg[0xF] = collided_rows;
+        } else if collided_rows > 0 {
             self.v_reg[0xF] = 1;
         } else {
             self.v_reg[0xF] = 0;
         }
+        Ok(())
     }
 
-    fn skip_keypress(&mut self, x: u16) {
+    fn skip_keypress(&mut self, x: u16) -> Result<(), EmuError> {
         let vx: u8 = self.v_reg[x as usize];
-        let key = self.keys[vx as usize];
+        let key = self.key(vx)?;
         if key {
-            self.pc += 2;
+            self.skip();
         }
+        Ok(())
     }
 
-    fn skip_keyrelease(&mut self, x: u16) {
+    fn skip_keyrelease(&mut self, x: u16) -> Result<(), EmuError> {
         let vx = self.v_reg[x as usize];
-        let key = self.keys[vx as usize];
+        let key = self.key(vx)?;
         if !key {
-            self.pc += 2;
+            self.skip();
         }
+        Ok(())
     }
 
     fn vx_eq_delaytimer(&mut self, x: u16) {
@@ -370,17 +898,14 @@ impl Emu {
     }
 
     fn wait(&mut self, x: u16) {
-        let mut pressed = false;
-        for i in 0..self.keys.len() {
-            if self.keys[i] {
-                self.v_reg[x as usize] = i as u8;
-                pressed = true;
-                break;
+        if self.quirks.key_wait_held {
+            if let Some(key) = self.keys.iter().position(|k| *k) {
+                self.v_reg[x as usize] = key as u8;
+                return;
             }
         }
-        if !pressed {
-            self.pc -= 2;
-        }
+        // `keypress` finishes the wait, `tick` does nothing until then
+        self.key_wait = Some(KeyWait::Press { x: x as u8 });
     }
 
     fn delaytimer_eq_vx(&mut self, x: u16) {
@@ -401,68 +926,177 @@ impl Emu {
         self.i_reg = c * 5;
     }
 
-    fn bcd(&mut self, x: u16) {
+    fn i_eq_bigfont(&mut self, x: u16) {
+        let c = (self.v_reg[x as usize] & 0xF) as u16;
+        self.i_reg = BIG_FONTSET_ADDR as u16 + c * 10;
+    }
+
+    fn i_eq_long(&mut self) -> Result<(), EmuError> {
+        let pc = self.pc as usize;
+        self.i_reg = (self.read(pc)? as u16) << 8 | self.read(pc + 1)? as u16;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    fn load_pattern(&mut self) -> Result<(), EmuError> {
+        let i = self.i_reg as usize;
+        let mut pattern = [0; PATTERN_SIZE];
+        for (idx, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.read(i + idx)?;
+        }
+        self.pattern = Some(pattern);
+        Ok(())
+    }
+
+    fn vx_vy_range(x: u16, y: u16) -> Vec<usize> {
+        let (x, y) = (x as usize, y as usize);
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
+
+    fn store_vx_vy(&mut self, x: u16, y: u16) -> Result<(), EmuError> {
+        let i = self.i_reg as usize;
+        for (offset, reg) in Self::vx_vy_range(x, y).into_iter().enumerate() {
+            self.write(i + offset, self.v_reg[reg])?;
+        }
+        Ok(())
+    }
+
+    fn ld_vx_vy(&mut self, x: u16, y: u16) -> Result<(), EmuError> {
+        let i = self.i_reg as usize;
+        for (offset, reg) in Self::vx_vy_range(x, y).into_iter().enumerate() {
+            self.v_reg[reg] = self.read(i + offset)?;
+        }
+        Ok(())
+    }
+
+    fn store_rpl(&mut self, x: u16) {
+        let x = x as usize;
+        self.rpl[..=x].copy_from_slice(&self.v_reg[..=x]);
+    }
+
+    fn ld_rpl(&mut self, x: u16) {
+        let x = x as usize;
+        self.v_reg[..=x].copy_from_slice(&self.rpl[..=x]);
+    }
+
+    fn bcd(&mut self, x: u16) -> Result<(), EmuError> {
         let vx = self.v_reg[x as usize] as f32;
         let hundreds = (vx / 100.0).floor() as u8;
         let tens = ((vx / 10.0) % 10.0).floor() as u8;
         let ones = (vx % 10.0) as u8;
-        self.ram[self.i_reg as usize] = hundreds;
-        self.ram[(self.i_reg + 1) as usize] = tens;
-        self.ram[(self.i_reg + 2) as usize] = ones;
+        let i = self.i_reg as usize;
+        self.write(i, hundreds)?;
+        self.write(i + 1, tens)?;
+        self.write(i + 2, ones)
     }
 
-    fn store_v0_vx(&mut self, x: u16) {
+    fn store_v0_vx(&mut self, x: u16) -> Result<(), EmuError> {
         let x = x as usize;
         let i = self.i_reg as usize;
         for idx in 0..=x {
-            self.ram[i + idx] = self.v_reg[idx];
+            self.write(i + idx, self.v_reg[idx])?;
+        }
+        if self.quirks.memory_increment {
+            self.i_reg = self.i_reg.wrapping_add(x as u16 + 1);
         }
+        Ok(())
     }
 
-    fn ld_v0_vx(&mut self, x: u16) {
+    fn ld_v0_vx(&mut self, x: u16) -> Result<(), EmuError> {
         let x = x as usize;
         let i = self.i_reg as usize;
         for idx in 0..=x {
-            self.v_reg[idx] = self.ram[i + idx];
+            self.v_reg[idx] = self.read(i + idx)?;
         }
+        if self.quirks.memory_increment {
+            self.i_reg = self.i_reg.wrapping_add(x as u16 + 1);
+        }
+        Ok(())
     }
-    #[cfg(test)]
-    pub fn get_pc(&mut self) -> u16 {
+    pub fn get_pc(&self) -> u16 {
         self.pc
     }
-    #[cfg(test)]
-    pub fn get_ram(&mut self) -> &[u8] {
+
+    pub fn get_ram(&self) -> &[u8] {
         &self.ram
     }
-    #[cfg(test)]
-    pub fn get_v_reg(&mut self) -> &[u8] {
+
+    pub fn get_v_reg(&self) -> &[u8] {
         &self.v_reg
     }
-    #[cfg(test)]
-    pub fn get_i_reg(&mut self) -> u16 {
+
+    pub fn get_i_reg(&self) -> u16 {
         self.i_reg
     }
-    #[cfg(test)]
-    pub fn get_sp(&mut self) -> u16 {
+
+    pub fn get_sp(&self) -> u16 {
         self.sp
     }
-    #[cfg(test)]
-    pub fn get_stack(&mut self) -> &[u16] {
+
+    pub fn get_stack(&self) -> &[u16] {
         &self.stack
     }
-    #[cfg(test)]
-    pub fn get_keys(&mut self) -> &[bool] {
+
+    pub fn get_keys(&self) -> &[bool] {
         &self.keys
     }
-    #[cfg(test)]
-    pub fn get_dt(&mut self) -> u8 {
+
+    pub fn get_dt(&self) -> u8 {
         self.dt
     }
 
-    #[cfg(test)]
-    pub fn get_st(&mut self) -> u8 {
+    pub fn get_st(&self) -> u8 {
         self.st
     }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn set_v_reg(&mut self, x: usize, val: u8) {
+        self.v_reg[x] = val;
+    }
+
+    pub fn set_i_reg(&mut self, val: u16) {
+        self.i_reg = val;
+    }
+
+    pub fn set_dt(&mut self, val: u8) {
+        self.dt = val;
+    }
+
+    pub fn set_st(&mut self, val: u8) {
+        self.st = val;
+    }
+
+    /// Pokes a byte into memory, for debuggers.
+    pub fn write_ram(&mut self, addr: usize, val: u8) -> Result<(), EmuError> {
+        self.write(addr, val)
+    }
+
+    /// Borrows a read-only view of the whole machine.
+    pub fn machine_state(&self) -> MachineState<'_> {
+        MachineState {
+            platform: self.platform,
+            quirks: self.quirks,
+            pc: self.pc,
+            i_reg: self.i_reg,
+            sp: self.sp,
+            dt: self.dt,
+            st: self.st,
+            v_reg: &self.v_reg,
+            stack: &self.stack,
+            ram: &self.ram,
+            keys: &self.keys,
+            key_wait: self.key_wait,
+            display: self.get_display(),
+            display_size: self.get_display_size(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -472,7 +1106,7 @@ mod tests {
     // tests initalization
     #[test]
     fn test_initialization() {
-        let mut emu = Emu::new();
+        let emu = Emu::new();
         let mut true_ram = [0; 4096];
         true_ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
         assert_eq!(emu.get_pc(), 0x200);
@@ -491,8 +1125,8 @@ mod tests {
         let mut emu = Emu::new();
         let mut expected = [0; 16];
         for i in 0..16 {
-            emu.push(i);
-            expected[i as usize] = i as u16;
+            emu.push(i).unwrap();
+            expected[i as usize] = i;
             assert_eq!(emu.get_sp(), i + 1);
             assert_eq!(emu.get_stack(), expected);
         }
@@ -503,11 +1137,11 @@ mod tests {
         let mut emu = Emu::new();
         let mut expected = [0; 16];
         for i in 0..16 {
-            emu.push(i);
-            expected[i as usize] = i as u16;
+            emu.push(i).unwrap();
+            expected[i as usize] = i;
         }
         for i in (0..16).rev() {
-            let val = emu.pop();
+            let val = emu.pop().unwrap();
             assert_eq!(val, i);
             assert_eq!(emu.get_sp(), i);
         }
@@ -522,7 +1156,7 @@ mod tests {
     #[test]
     fn test_call_nnn() {
         let mut emu = Emu::new();
-        emu.call_nnn(0xFFF);
+        emu.call_nnn(0xFFF).unwrap();
         assert_eq!(emu.get_pc(), 0xFFF);
         assert_eq!(emu.get_sp(), 1);
         assert_eq!(emu.get_stack()[0], 0x200);
@@ -593,4 +1227,357 @@ mod tests {
         let v_reg = emu.get_v_reg();
         assert_eq!(v_reg[0], 0xFF);
     }
+    #[test]
+    fn test_stack_overflow() {
+        let mut emu = Emu::new();
+        for i in 0..16 {
+            emu.push(i).unwrap();
+        }
+        assert_eq!(emu.push(16), Err(EmuError::StackOverflow));
+    }
+    #[test]
+    fn test_stack_underflow() {
+        let mut emu = Emu::new();
+        assert_eq!(emu.pop(), Err(EmuError::StackUnderflow));
+    }
+    #[test]
+    fn test_unknown_opcode() {
+        let mut emu = Emu::new();
+        emu.load(&[0xFF, 0xFF]).unwrap();
+        assert_eq!(
+            emu.tick(),
+            Err(EmuError::UnknownOpcode {
+                pc: 0x200,
+                op: 0xFFFF
+            })
+        );
+    }
+    #[test]
+    fn test_fetch_out_of_bounds() {
+        let mut emu = Emu::new();
+        emu.jmp_nnn(0xFFF);
+        assert_eq!(
+            emu.tick(),
+            Err(EmuError::MemoryOutOfBounds { addr: 0x1000 })
+        );
+    }
+    #[test]
+    fn test_store_out_of_bounds() {
+        let mut emu = Emu::new();
+        emu.i_eq_nnn(0xAFFE);
+        assert_eq!(
+            emu.store_v0_vx(3),
+            Err(EmuError::MemoryOutOfBounds { addr: 0x1000 })
+        );
+    }
+    #[test]
+    fn test_shift_quirk() {
+        let mut emu = Emu::with_quirks(Quirks::cosmac_vip());
+        emu.vx_eqnn(0x6103, 1);
+        emu.vx_bitshiftright(0, 1);
+        assert_eq!(emu.get_v_reg()[0], 0x01);
+        assert_eq!(emu.get_v_reg()[0xF], 1);
+        let mut emu = Emu::with_quirks(Quirks::schip());
+        emu.vx_eqnn(0x6103, 1);
+        emu.vx_bitshiftright(0, 1);
+        assert_eq!(emu.get_v_reg()[0], 0);
+    }
+    #[test]
+    fn test_memory_increment_quirk() {
+        let mut emu = Emu::with_quirks(Quirks::cosmac_vip());
+        emu.i_eq_nnn(0xA300);
+        emu.store_v0_vx(3).unwrap();
+        assert_eq!(emu.get_i_reg(), 0x304);
+        let mut emu = Emu::with_quirks(Quirks::modern());
+        emu.i_eq_nnn(0xA300);
+        emu.ld_v0_vx(3).unwrap();
+        assert_eq!(emu.get_i_reg(), 0x300);
+    }
+    #[test]
+    fn test_jump_quirk() {
+        let mut emu = Emu::with_quirks(Quirks::schip());
+        emu.vx_eqnn(0x6204, 2);
+        emu.jmp_vzero_plusnnn(0xB230);
+        assert_eq!(emu.get_pc(), 0x234);
+    }
+    #[test]
+    fn test_vf_reset_quirk() {
+        let mut emu = Emu::with_quirks(Quirks::cosmac_vip());
+        emu.vx_eqnn(0x6F01, 0xF);
+        emu.vx_or_vy(0, 1);
+        assert_eq!(emu.get_v_reg()[0xF], 0);
+    }
+    #[test]
+    fn test_clipping_quirk() {
+        let mut emu = Emu::with_quirks(Quirks::schip());
+        emu.vx_eqnn(0x603E, 0);
+        emu.i_eq_font(1);
+        emu.draw(0, 1, 5).unwrap();
+        assert!(!emu.get_display()[0]);
+        let mut emu = Emu::with_quirks(Quirks::modern());
+        emu.vx_eqnn(0x603E, 0);
+        emu.i_eq_font(1);
+        emu.draw(0, 1, 5).unwrap();
+        assert!(emu.get_display()[0]);
+    }
+    #[test]
+    fn test_display_wait_quirk() {
+        let mut emu = Emu::with_quirks(Quirks::cosmac_vip());
+        emu.load(&[0xD0, 0x15, 0xD0, 0x15]).unwrap();
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert_eq!(emu.get_pc(), 0x202);
+        emu.tick_timers();
+        emu.tick().unwrap();
+        assert_eq!(emu.get_pc(), 0x204);
+    }
+    #[test]
+    fn test_schip_opcodes_need_platform() {
+        let mut emu = Emu::new();
+        emu.load(&[0x00, 0xFF]).unwrap();
+        assert_eq!(
+            emu.tick(),
+            Err(EmuError::UnknownOpcode {
+                pc: 0x200,
+                op: 0x00FF
+            })
+        );
+    }
+    #[test]
+    fn test_hires() {
+        let mut emu = Emu::with_platform(Platform::SuperChip, Quirks::schip());
+        assert_eq!(emu.get_display_size(), (64, 32));
+        emu.load(&[0x00, 0xFF, 0x00, 0xFE]).unwrap();
+        emu.tick().unwrap();
+        assert_eq!(emu.get_display_size(), (128, 64));
+        assert_eq!(emu.get_display().len(), 128 * 64);
+        emu.tick().unwrap();
+        assert_eq!(emu.get_display_size(), (64, 32));
+    }
+    #[test]
+    fn test_big_sprite() {
+        let mut emu = Emu::with_platform(Platform::SuperChip, Quirks::schip());
+        emu.set_hires(true);
+        emu.ram[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        emu.i_eq_nnn(0xA300);
+        emu.draw(0, 0, 0).unwrap();
+        let display = emu.get_display();
+        assert!(display[0] && display[15] && !display[16]);
+        assert!(display[HIRES_WIDTH * 15] && !display[HIRES_WIDTH * 16]);
+        emu.draw(0, 0, 0).unwrap();
+        assert_eq!(emu.get_v_reg()[0xF], 16);
+    }
+    #[test]
+    fn test_xo_hires_collision_flag() {
+        let mut emu = Emu::with_platform(Platform::XoChip, Quirks::xo_chip());
+        emu.set_hires(true);
+        emu.ram[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        emu.i_eq_nnn(0xA300);
+        emu.draw(0, 0, 0).unwrap();
+        assert_eq!(emu.get_v_reg()[0xF], 0);
+        emu.draw(0, 0, 0).unwrap();
+        assert_eq!(emu.get_v_reg()[0xF], 1);
+    }
+    #[test]
+    fn test_scroll() {
+        let mut emu = Emu::with_platform(Platform::SuperChip, Quirks::schip());
+        emu.i_eq_font(0);
+        emu.draw(1, 1, 1).unwrap();
+        emu.scroll_down(2);
+        assert!(!emu.get_display()[0]);
+        assert!(emu.get_display()[SCREEN_WIDTH * 2]);
+        emu.scroll_right(4);
+        assert!(emu.get_display()[SCREEN_WIDTH * 2 + 4]);
+        emu.scroll_left(4);
+        assert!(emu.get_display()[SCREEN_WIDTH * 2]);
+        assert!(!emu.get_display()[SCREEN_WIDTH * 2 + 4]);
+    }
+    #[test]
+    fn test_exit() {
+        let mut emu = Emu::with_platform(Platform::SuperChip, Quirks::schip());
+        emu.load(&[0x00, 0xFD]).unwrap();
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert!(emu.has_exited());
+        assert_eq!(emu.get_pc(), 0x202);
+    }
+    #[test]
+    fn test_rpl_flags() {
+        let mut emu = Emu::with_platform(Platform::SuperChip, Quirks::schip());
+        emu.vx_eqnn(0x6207, 2);
+        emu.store_rpl(2);
+        emu.reset();
+        emu.ld_rpl(2);
+        assert_eq!(emu.get_v_reg()[2], 7);
+    }
+    #[test]
+    fn test_xo_memory() {
+        let mut emu = Emu::with_platform(Platform::XoChip, Quirks::xo_chip());
+        assert_eq!(emu.get_ram().len(), 0x10000);
+        emu.load(&[0xF0, 0x00, 0xFF, 0xF0, 0xF3, 0x55]).unwrap();
+        emu.vx_eqnn(0x6342, 3);
+        emu.tick().unwrap();
+        assert_eq!(emu.get_i_reg(), 0xFFF0);
+        assert_eq!(emu.get_pc(), 0x204);
+        emu.tick().unwrap();
+        assert_eq!(emu.get_ram()[0xFFF3], 0x42);
+    }
+    #[test]
+    fn test_xo_skip_long() {
+        let mut emu = Emu::with_platform(Platform::XoChip, Quirks::xo_chip());
+        emu.load(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34]).unwrap();
+        emu.tick().unwrap();
+        assert_eq!(emu.get_pc(), 0x206);
+    }
+    #[test]
+    fn test_xo_register_range() {
+        let mut emu = Emu::with_platform(Platform::XoChip, Quirks::xo_chip());
+        emu.vx_eqnn(0x6201, 2);
+        emu.vx_eqnn(0x6302, 3);
+        emu.vx_eqnn(0x6403, 4);
+        emu.i_eq_nnn(0xA300);
+        emu.store_vx_vy(4, 2).unwrap();
+        assert_eq!(&emu.get_ram()[0x300..0x303], &[3, 2, 1]);
+        emu.ld_vx_vy(5, 7).unwrap();
+        assert_eq!(&emu.get_v_reg()[5..8], &[3, 2, 1]);
+        assert_eq!(emu.get_i_reg(), 0x300);
+    }
+    #[test]
+    fn test_xo_planes() {
+        let mut emu = Emu::with_platform(Platform::XoChip, Quirks::xo_chip());
+        emu.ram[0x300] = 0x80;
+        emu.ram[0x301] = 0x40;
+        emu.i_eq_nnn(0xA300);
+        emu.load(&[0xF3, 0x01, 0xD0, 0x01, 0xF2, 0x01, 0x00, 0xE0]).unwrap();
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert!(emu.get_plane(0)[0] && !emu.get_plane(0)[1]);
+        assert!(emu.get_plane(1)[1] && !emu.get_plane(1)[0]);
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert!(emu.get_plane(0)[0]);
+        assert!(!emu.get_plane(1)[1]);
+    }
+    #[test]
+    fn test_xo_audio_registers() {
+        let mut emu = Emu::with_platform(Platform::XoChip, Quirks::xo_chip());
+        emu.ram[0x300..0x310].copy_from_slice(&[0xAA; 16]);
+        emu.i_eq_nnn(0xA300);
+        emu.vx_eqnn(0x6170, 1);
+        emu.load(&[0xF0, 0x02, 0xF1, 0x3A]).unwrap();
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert_eq!(emu.pattern, Some([0xAA; 16]));
+        assert_eq!(emu.pitch, 0x70);
+    }
+    #[test]
+    fn test_is_beeping() {
+        let mut emu = Emu::new();
+        emu.vx_eqnn(0x6002, 0);
+        emu.soundtimer_eq_vx(0);
+        assert!(emu.is_beeping());
+        emu.tick_timers();
+        assert!(emu.audio_state().beeping);
+        emu.tick_timers();
+        assert!(!emu.is_beeping());
+    }
+    #[test]
+    fn test_load_too_big() {
+        let mut emu = Emu::new();
+        let rom = vec![0xAA; 0x1000 - 0x200 + 1];
+        assert_eq!(
+            emu.load(&rom),
+            Err(EmuError::MemoryOutOfBounds { addr: 0x1000 })
+        );
+        assert_eq!(emu.get_ram()[0x200], 0);
+        emu.load(&rom[1..]).unwrap();
+        assert_eq!(emu.get_ram()[0xFFF], 0xAA);
+    }
+    #[test]
+    fn test_seeded_rand() {
+        let rom = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF];
+        let mut a = Emu::with_seed(7);
+        let mut b = Emu::with_platform(Platform::SuperChip, Quirks::schip());
+        b.reseed(7);
+        a.load(&rom).unwrap();
+        b.load(&rom).unwrap();
+        for _ in 0..3 {
+            a.tick().unwrap();
+            b.tick().unwrap();
+        }
+        assert_eq!(a.get_v_reg(), b.get_v_reg());
+    }
+    #[test]
+    fn test_machine_state() {
+        let mut emu = Emu::new();
+        emu.load(&[0x63, 0x1F, 0x23, 0x00]).unwrap();
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        let state = emu.machine_state();
+        assert_eq!(state.pc, 0x300);
+        assert_eq!(state.v_reg[3], 0x1F);
+        assert_eq!(state.call_stack(), &[0x204]);
+        assert_eq!(state.ram[0x200], 0x63);
+        assert_eq!(state.display_size, (SCREEN_WIDTH, SCREEN_HEIGHT));
+    }
+    #[test]
+    fn test_setters() {
+        let mut emu = Emu::new();
+        emu.set_pc(0x300);
+        emu.set_v_reg(3, 0x10);
+        emu.set_i_reg(0x400);
+        emu.write_ram(0x400, 0xAB).unwrap();
+        assert_eq!(emu.get_pc(), 0x300);
+        assert_eq!(emu.get_v_reg()[3], 0x10);
+        assert_eq!(emu.get_ram()[0x400], 0xAB);
+        assert!(emu.write_ram(0x1000, 0).is_err());
+    }
+    #[test]
+    fn test_key_wait_press_then_release() {
+        let mut emu = Emu::new();
+        // LD V3, K; LD V4, 0x99
+        emu.load(&[0xF3, 0x0A, 0x64, 0x99]).unwrap();
+        emu.keypress(2, true).unwrap();
+        emu.set_dt(5);
+        emu.tick().unwrap();
+        assert_eq!(emu.key_wait(), Some(KeyWait::Press { x: 3 }));
+        // a key held from before doesn't count, even when released
+        emu.keypress(2, false).unwrap();
+        emu.keypress(7, true).unwrap();
+        assert_eq!(emu.key_wait(), Some(KeyWait::Release { x: 3, key: 7 }));
+        emu.tick().unwrap();
+        emu.tick_timers();
+        assert_eq!(emu.get_pc(), 0x202);
+        assert_eq!(emu.get_v_reg()[4], 0);
+        assert_eq!(emu.get_dt(), 4);
+
+        emu.keypress(7, false).unwrap();
+        assert_eq!(emu.key_wait(), None);
+        assert_eq!(emu.get_v_reg()[3], 7);
+        emu.tick().unwrap();
+        assert_eq!(emu.get_v_reg()[4], 0x99);
+    }
+    #[test]
+    fn test_key_wait_held_quirk() {
+        let quirks = Quirks {
+            key_wait_held: true,
+            ..Quirks::modern()
+        };
+        let mut emu = Emu::with_quirks(quirks);
+        emu.load(&[0xF3, 0x0A, 0xF4, 0x0A]).unwrap();
+        emu.keypress(2, true).unwrap();
+        emu.tick().unwrap();
+        assert_eq!(emu.get_v_reg()[3], 2);
+        emu.keypress(2, false).unwrap();
+        emu.tick().unwrap();
+        emu.keypress(9, true).unwrap();
+        assert_eq!(emu.key_wait(), None);
+        assert_eq!(emu.get_v_reg()[4], 9);
+    }
+    #[test]
+    fn test_invalid_key() {
+        let mut emu = Emu::new();
+        assert_eq!(emu.keypress(16, true), Err(EmuError::InvalidKey { key: 16 }));
+        assert!(emu.get_keys().iter().all(|key| !key));
+    }
 }
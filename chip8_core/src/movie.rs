@@ -0,0 +1,346 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::state::crc32;
+use crate::{Emu, EmuError, Platform, Quirks, NUM_KEYS};
+
+// Movies are plain text so they can be read, diffed and attached to bug
+// reports: a header of settings, then one line per key event.
+//
+//   chip8-movie 1
+//   rom 3f2a9c1b
+//   platform chip8
+//   quirks shift=1 memory_increment=0 jump=0 vf_reset=0 clipping=0 display_wait=0 key_wait_held=0
+//   seed 42
+//   ips 1200
+//   12 245 5 down
+//
+// An event line is the frame and tick counts at which the key changed, the key
+// in hex, and down or up.
+const MAGIC: &str = "chip8-movie";
+const VERSION: u32 = 1;
+const QUIRK_NAMES: [&str; 7] = [
+    "shift",
+    "memory_increment",
+    "jump",
+    "vf_reset",
+    "clipping",
+    "display_wait",
+    "key_wait_held",
+];
+
+/// One `Emu::keypress` call and where in the run it happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// `Emu::frame_count` at the time of the call.
+    pub frame: u64,
+    /// `Emu::tick_count` at the time of the call.
+    pub tick: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// Everything needed to replay a run bit for bit: the ROM it was recorded
+/// on, the machine settings, and every key press and release.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_crc: u32,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub seed: u64,
+    /// Instructions per second the run was driven at. Key events are placed
+    /// by tick and frame counts, so playback must interleave them the same way.
+    pub ips: u32,
+    pub events: Vec<KeyEvent>,
+}
+
+/// Reasons a movie could not be read or played.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    Parse { line: usize, message: String },
+    RomMismatch { expected: u32, found: u32 },
+    /// The ROM doesn't fit in the recorded platform's memory, or an event
+    /// presses a key past F.
+    Load(EmuError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Parse { line, message } => write!(f, "{}: {}", line, message),
+            MovieError::RomMismatch { expected, found } => write!(
+                f,
+                "movie was recorded on ROM {:08x}, this one is {:08x}",
+                expected, found
+            ),
+            MovieError::Load(err) => write!(f, "could not boot movie: {}", err),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl Movie {
+    /// An empty movie for `rom` run with these settings.
+    pub fn new(rom: &[u8], platform: Platform, quirks: Quirks, seed: u64, ips: u32) -> Self {
+        Self {
+            rom_crc: crc32(rom),
+            platform,
+            quirks,
+            seed,
+            ips,
+            events: Vec::new(),
+        }
+    }
+
+    /// A freshly booted machine with `rom` loaded, set up as when the movie
+    /// was recorded and ready to play it back.
+    pub fn boot(&self, rom: &[u8]) -> Result<Emu, MovieError> {
+        let found = crc32(rom);
+        if found != self.rom_crc {
+            return Err(MovieError::RomMismatch {
+                expected: self.rom_crc,
+                found,
+            });
+        }
+        let mut emu = Emu::with_platform(self.platform, self.quirks);
+        emu.reseed(self.seed);
+        emu.load(rom).map_err(MovieError::Load)?;
+        emu.start_playback(self).map_err(MovieError::Load)?;
+        Ok(emu)
+    }
+
+    /// The frame of the last event, after which playback has nothing left to do.
+    pub fn last_frame(&self) -> u64 {
+        self.events.last().map_or(0, |event| event.frame)
+    }
+
+    pub fn to_text(&self) -> String {
+        let quirks: Vec<String> = QUIRK_NAMES
+            .iter()
+            .zip(quirk_flags(&self.quirks))
+            .map(|(name, on)| format!("{}={}", name, on as u8))
+            .collect();
+        let mut out = format!(
+            "{} {}\nrom {:08x}\nplatform {}\nquirks {}\nseed {}\nips {}\n",
+            MAGIC,
+            VERSION,
+            self.rom_crc,
+            self.platform.name(),
+            quirks.join(" "),
+            self.seed,
+            self.ips
+        );
+        for event in &self.events {
+            out.push_str(&format!(
+                "{} {} {:x} {}\n",
+                event.frame,
+                event.tick,
+                event.key,
+                if event.pressed { "down" } else { "up" }
+            ));
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Self, MovieError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| (idx + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        let mut header = |name: &str| -> Result<(usize, &str), MovieError> {
+            let (line, text) = lines.next().ok_or_else(|| MovieError::Parse {
+                line: 0,
+                message: format!("missing {}", name),
+            })?;
+            match text.split_once(' ') {
+                Some((key, value)) if key == name => Ok((line, value.trim())),
+                _ => Err(parse_error(line, format!("expected {}", name))),
+            }
+        };
+
+        let (line, version) = header(MAGIC)?;
+        if version.parse() != Ok(VERSION) {
+            return Err(parse_error(line, format!("unsupported version {}", version)));
+        }
+        let (line, rom) = header("rom")?;
+        let rom_crc =
+            u32::from_str_radix(rom, 16).map_err(|_| parse_error(line, "bad ROM checksum"))?;
+        let (line, platform) = header("platform")?;
+        let platform =
+            Platform::from_name(platform).ok_or_else(|| parse_error(line, "unknown platform"))?;
+        let (line, quirks) = header("quirks")?;
+        let quirks = parse_quirks(quirks).ok_or_else(|| parse_error(line, "bad quirks"))?;
+        let (line, seed) = header("seed")?;
+        let seed = seed.parse().map_err(|_| parse_error(line, "bad seed"))?;
+        let (line, ips) = header("ips")?;
+        let ips = ips.parse().map_err(|_| parse_error(line, "bad ips"))?;
+
+        let mut events: Vec<KeyEvent> = Vec::new();
+        for (line, text) in lines {
+            let event = parse_event(text)
+                .ok_or_else(|| parse_error(line, "expected 'frame tick key down|up'"))?;
+            if events
+                .last()
+                .is_some_and(|last| (event.frame, event.tick) < (last.frame, last.tick))
+            {
+                return Err(parse_error(line, "events out of order"));
+            }
+            events.push(event);
+        }
+        Ok(Self {
+            rom_crc,
+            platform,
+            quirks,
+            seed,
+            ips,
+            events,
+        })
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> MovieError {
+    MovieError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+fn parse_event(text: &str) -> Option<KeyEvent> {
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    let [frame, tick, key, action] = fields[..] else {
+        return None;
+    };
+    let key = u8::from_str_radix(key, 16).ok()?;
+    if key as usize >= NUM_KEYS {
+        return None;
+    }
+    Some(KeyEvent {
+        frame: frame.parse().ok()?,
+        tick: tick.parse().ok()?,
+        key,
+        pressed: match action {
+            "down" => true,
+            "up" => false,
+            _ => return None,
+        },
+    })
+}
+
+fn quirk_flags(q: &Quirks) -> [bool; QUIRK_NAMES.len()] {
+    [
+        q.shift,
+        q.memory_increment,
+        q.jump,
+        q.vf_reset,
+        q.clipping,
+        q.display_wait,
+        q.key_wait_held,
+    ]
+}
+
+fn parse_quirks(text: &str) -> Option<Quirks> {
+    let mut flags = [false; QUIRK_NAMES.len()];
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    if fields.len() != flags.len() {
+        return None;
+    }
+    for (field, name) in fields.iter().zip(QUIRK_NAMES) {
+        let value = field.strip_prefix(name)?.strip_prefix('=')?;
+        let idx = QUIRK_NAMES.iter().position(|n| *n == name)?;
+        flags[idx] = match value {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        };
+    }
+    let [shift, memory_increment, jump, vf_reset, clipping, display_wait, key_wait_held] = flags;
+    Some(Quirks {
+        shift,
+        memory_increment,
+        jump,
+        vf_reset,
+        clipping,
+        display_wait,
+        key_wait_held,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Scheduler;
+    use std::time::Duration;
+
+    // waits for a key, then draws a random byte's worth of sprite at VX, VX
+    const ROM: [u8; 10] = [0xF0, 0x0A, 0xC1, 0xFF, 0xA0, 0x00, 0xD1, 0x15, 0x12, 0x00];
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut movie = Movie::new(&ROM, Platform::XoChip, Quirks::xo_chip(), 99, 1000);
+        movie.events.push(KeyEvent {
+            frame: 3,
+            tick: 60,
+            key: 0xA,
+            pressed: true,
+        });
+        let text = movie.to_text();
+        assert!(text.contains("\n3 60 a down\n"));
+        assert_eq!(Movie::parse(&text), Ok(movie));
+
+        let broken = text.replace("3 60 a down", "3 60 g down");
+        assert_eq!(
+            Movie::parse(&broken),
+            Err(parse_error(7, "expected 'frame tick key down|up'"))
+        );
+    }
+
+    #[test]
+    fn test_playback_is_bit_exact() {
+        let frame = Duration::from_nanos(16_666_667);
+        let movie = Movie::new(&ROM, Platform::Chip8, Quirks::modern(), 7, 1200);
+        let mut emu = Emu::with_seed(7);
+        emu.load(&ROM).unwrap();
+        emu.start_recording(movie);
+
+        // the recording frontend runs on wall-clock time in uneven slices
+        let mut sched = Scheduler::new(1200);
+        let mut elapsed = Duration::ZERO;
+        for step in 0..40u64 {
+            let slice = Duration::from_micros(3_000 + step * 997);
+            sched.advance(&mut emu, slice).unwrap();
+            elapsed += slice;
+            if step % 7 == 3 {
+                emu.keypress((step % 16) as usize, true).unwrap();
+            }
+            if step % 7 == 5 {
+                emu.keypress(((step - 2) % 16) as usize, false).unwrap();
+            }
+        }
+        // finish on a frame boundary, 54 frames in
+        sched
+            .advance(&mut emu, Duration::from_millis(900) - elapsed)
+            .unwrap();
+        assert_eq!(emu.frame_count(), 54);
+        let recorded = emu.save_state();
+        let movie = emu.stop_recording().unwrap();
+        assert_eq!(movie.events.len(), 11);
+
+        // playback advances a frame at a time and lands on the same state
+        let mut replay = Movie::parse(&movie.to_text()).unwrap().boot(&ROM).unwrap();
+        let mut sched = Scheduler::new(movie.ips);
+        while replay.frame_count() < emu.frame_count() {
+            sched.advance(&mut replay, frame).unwrap();
+        }
+        assert!(!replay.is_playing());
+        assert_eq!(replay.tick_count(), emu.tick_count());
+        assert_eq!(replay.frame_count(), emu.frame_count());
+        assert_eq!(replay.save_state(), recorded);
+
+        assert!(matches!(
+            movie.boot(&ROM[..8]),
+            Err(MovieError::RomMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,1174 @@
+//! Compiler for Octo (`.8o`), the high-level assembly most CHIP-8 homebrew is written in.
+//!
+//! ```text
+//! : main
+//!   v0 := 0
+//!   loop
+//!     i := digit
+//!     sprite v0 v1 5
+//!     v0 += 5
+//!     if v0 == 40 then return
+//!   again
+//! : digit
+//!   0xF0 0x90 0x90 0x90 0xF0
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use crate::Platform;
+
+const START_ADDR: u16 = 0x200;
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// Output of a successful compile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub rom: Vec<u8>,
+    /// Labels with their addresses, in address order.
+    pub labels: Vec<(String, u16)>,
+    /// Named `:breakpoint`s with their addresses.
+    pub breakpoints: Vec<(String, u16)>,
+    /// Source line that produced the bytes starting at each address, in address order.
+    pub lines: Vec<(u16, usize)>,
+}
+
+impl Program {
+    /// Source line of the instruction or data byte at `addr`.
+    pub fn line_at(&self, addr: u16) -> Option<usize> {
+        if addr < START_ADDR || (addr - START_ADDR) as usize >= self.rom.len() {
+            return None;
+        }
+        let idx = self.lines.partition_point(|(start, _)| *start <= addr);
+        idx.checked_sub(1).map(|idx| self.lines[idx].1)
+    }
+}
+
+/// The first problem found in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OctoError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for OctoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for OctoError {}
+
+/// Compiles Octo source into a ROM for `platform`, rejecting instructions it lacks.
+pub fn compile(source: &str, platform: Platform) -> Result<Program, OctoError> {
+    let mut compiler = Compiler::new(tokenize(source)?, platform);
+    while !compiler.tokens.is_empty() {
+        compiler.statement()?;
+    }
+    compiler.finish()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: impl Into<String>) -> OctoError {
+        OctoError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<VecDeque<Token>, OctoError> {
+    let mut tokens = VecDeque::new();
+    for (idx, line) in source.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut pos = 0;
+        while pos < chars.len() {
+            let column = pos + 1;
+            if chars[pos] == '#' {
+                break;
+            } else if chars[pos].is_whitespace() {
+                pos += 1;
+                continue;
+            }
+            let start = pos;
+            if chars[pos] == '"' {
+                pos += 1;
+                while pos < chars.len() && chars[pos] != '"' {
+                    pos += 1;
+                }
+                if pos == chars.len() {
+                    return Err(OctoError {
+                        line: idx + 1,
+                        column,
+                        message: "unterminated string".to_string(),
+                    });
+                }
+                pos += 1;
+            } else {
+                while pos < chars.len() && !chars[pos].is_whitespace() {
+                    pos += 1;
+                }
+            }
+            tokens.push_back(Token {
+                text: chars[start..pos].iter().collect(),
+                line: idx + 1,
+                column,
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or(digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Reg(u8),
+    Byte(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Fixup {
+    // low 12 bits of the opcode at the address
+    Addr12,
+    // the two bytes at the address
+    Addr16,
+    // second byte of `v0 := hi`, with the nibble for short `:unpack`
+    UnpackHi(Option<u8>),
+    UnpackLo,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+struct Loop {
+    token: Token,
+    start: u16,
+    exits: Vec<u16>,
+}
+
+struct Compiler {
+    platform: Platform,
+    tokens: VecDeque<Token>,
+    last: Token,
+    line: usize,
+    rom: Vec<u8>,
+    here: u32,
+    labels: HashMap<String, u16>,
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    fixups: Vec<(u16, Token, Fixup)>,
+    branches: Vec<(Token, u16)>,
+    loops: Vec<Loop>,
+    breakpoints: Vec<(String, u16)>,
+    lines: Vec<(u16, usize)>,
+    line_end: u32,
+    main_jump: bool,
+}
+
+impl Compiler {
+    fn new(tokens: VecDeque<Token>, platform: Platform) -> Self {
+        let start = Token {
+            text: "main".to_string(),
+            line: 1,
+            column: 1,
+        };
+        let mut compiler = Self {
+            platform,
+            tokens,
+            last: start.clone(),
+            line: 1,
+            rom: Vec::new(),
+            here: START_ADDR as u32,
+            labels: HashMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+            breakpoints: Vec::new(),
+            lines: Vec::new(),
+            line_end: 0,
+            main_jump: true,
+        };
+        // execution starts at main; this jump is dropped again if main comes first
+        compiler.fixups.push((START_ADDR, start, Fixup::Addr12));
+        compiler.rom.extend([0x10, 0x00]);
+        compiler.here += 2;
+        compiler
+    }
+
+    fn next(&mut self) -> Result<Token, OctoError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.last = token.clone();
+                Ok(token)
+            }
+            None => Err(self.last.error("unexpected end of file")),
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|t| t.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, OctoError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(token.error(format!("expected '{}', found '{}'", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn statement(&mut self) -> Result<(), OctoError> {
+        let token = self.next()?;
+        self.line = token.line;
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.define_label(&name, self.here)?;
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let reg = self.register()?;
+                self.aliases.insert(name.text, reg);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.next()?;
+                let value = self.number(&value)?;
+                self.define_const(&name, value)?;
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.define_const(&name, value)?;
+            }
+            ":macro" => self.define_macro()?,
+            ":unpack" => self.unpack()?,
+            ":next" => {
+                let name = self.name()?;
+                self.define_label(&name, self.here + 1)?;
+            }
+            ":org" => {
+                let addr = self.next()?;
+                let value = self.number(&addr)? as i64;
+                if value < START_ADDR as i64 || value >= self.platform.ram_size() as i64 {
+                    return Err(addr.error("address outside program memory"));
+                }
+                self.here = value as u32;
+            }
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    self.calc()?
+                } else {
+                    let value = self.next()?;
+                    self.number(&value)?
+                };
+                let byte = byte_in_range(&self.last, value.floor() as i64)?;
+                self.emit(&[byte])?;
+            }
+            ":breakpoint" => {
+                let name = self.name()?;
+                self.breakpoints.push((name.text, self.here as u16));
+            }
+            ":call" => self.op_addr(0x2000)?,
+            ";" | "return" => self.op(0x00EE)?,
+            "clear" => self.op(0x00E0)?,
+            "exit" => {
+                self.require_schip(&token)?;
+                self.op(0x00FD)?;
+            }
+            "lores" => {
+                self.require_schip(&token)?;
+                self.op(0x00FE)?;
+            }
+            "hires" => {
+                self.require_schip(&token)?;
+                self.op(0x00FF)?;
+            }
+            "scroll-right" => {
+                self.require_schip(&token)?;
+                self.op(0x00FB)?;
+            }
+            "scroll-left" => {
+                self.require_schip(&token)?;
+                self.op(0x00FC)?;
+            }
+            "scroll-down" => {
+                self.require_schip(&token)?;
+                let n = self.nibble()?;
+                self.op(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                self.require_xo(&token)?;
+                let n = self.nibble()?;
+                self.op(0x00D0 | n)?;
+            }
+            "bcd" => self.op_x(0xF033)?,
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek_is("-") {
+                    self.require_xo(&token)?;
+                    self.next()?;
+                    let y = self.register()?;
+                    let base = if token.text == "save" { 0x5002 } else { 0x5003 };
+                    self.op(base | (x as u16) << 8 | (y as u16) << 4)?;
+                } else {
+                    let base = if token.text == "save" { 0xF055 } else { 0xF065 };
+                    self.op(base | (x as u16) << 8)?;
+                }
+            }
+            "saveflags" | "loadflags" => {
+                self.require_schip(&token)?;
+                self.op_x(if token.text == "saveflags" {
+                    0xF075
+                } else {
+                    0xF085
+                })?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.op(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n)?;
+            }
+            "jump" => self.op_addr(0x1000)?,
+            "jump0" => self.op_addr(0xB000)?,
+            "native" => self.op_addr(0x0000)?,
+            "plane" => {
+                self.require_xo(&token)?;
+                let n = self.next()?;
+                let value = self.int(&n)?;
+                if !(0..=3).contains(&value) {
+                    return Err(n.error("plane mask must be 0 to 3"));
+                }
+                self.op(0xF001 | (value as u16) << 8)?;
+            }
+            "audio" => {
+                self.require_xo(&token)?;
+                self.op(0xF002)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                if token.text == "pitch" {
+                    self.require_xo(&token)?;
+                }
+                self.expect(":=")?;
+                self.op_x(match token.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                })?;
+            }
+            "i" => self.assign_i()?,
+            "if" => self.if_statement()?,
+            "else" => {
+                let (_, jump) = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| token.error("else without a matching if ... begin"))?;
+                let at = self.here as u16;
+                self.op(0x1000)?;
+                self.patch_jump(jump, &token)?;
+                self.branches.push((token, at));
+            }
+            "end" => {
+                let (_, jump) = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| token.error("end without a matching if ... begin"))?;
+                self.patch_jump(jump, &token)?;
+            }
+            "loop" => self.loops.push(Loop {
+                token,
+                start: self.here as u16,
+                exits: Vec::new(),
+            }),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(token.error("while outside of a loop"));
+                }
+                self.condition(true)?;
+                let at = self.here as u16;
+                self.op(0x1000)?;
+                self.loops.last_mut().unwrap().exits.push(at);
+            }
+            "again" => {
+                let lp = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| token.error("again without a matching loop"))?;
+                self.op(0x1000 | lp.start)?;
+                for exit in lp.exits {
+                    self.patch_jump(exit, &token)?;
+                }
+            }
+            _ => {
+                if let Some(x) = self.reg(&token.text) {
+                    self.assign_register(x)?;
+                } else if self.macros.contains_key(&token.text) {
+                    self.expand(&token)?;
+                } else if parse_number(&token.text).is_some()
+                    || self.consts.contains_key(&token.text)
+                {
+                    let value = self.int(&token)?;
+                    let byte = byte_in_range(&token, value)?;
+                    self.emit(&[byte])?;
+                } else if token.text.starts_with(':') || token.text.starts_with('"') {
+                    return Err(token.error(format!("unknown directive '{}'", token.text)));
+                } else {
+                    // anything else names a subroutine, possibly defined later
+                    self.tokens.push_front(token);
+                    self.op_addr(0x2000)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Program, OctoError> {
+        if let Some((token, _)) = self.branches.last() {
+            return Err(token.error("if ... begin without a matching end"));
+        }
+        if let Some(lp) = self.loops.last() {
+            return Err(lp.token.error("loop without a matching again"));
+        }
+        for (at, token, fixup) in std::mem::take(&mut self.fixups) {
+            let addr = match self.labels.get(&token.text) {
+                Some(addr) => *addr,
+                None if self.main_jump && at == START_ADDR && token.text == "main" => {
+                    return Err(token.error("program has no main label"));
+                }
+                None => return Err(token.error(format!("undefined name '{}'", token.text))),
+            };
+            let idx = (at - START_ADDR) as usize;
+            match fixup {
+                Fixup::Addr12 => {
+                    if addr > 0xFFF {
+                        return Err(token.error(format!("{} is past 0xFFF", token.text)));
+                    }
+                    self.rom[idx] |= (addr >> 8) as u8;
+                    self.rom[idx + 1] = addr as u8;
+                }
+                Fixup::Addr16 => {
+                    self.rom[idx..idx + 2].copy_from_slice(&addr.to_be_bytes());
+                }
+                Fixup::UnpackHi(nibble) => {
+                    self.rom[idx + 1] = match nibble {
+                        Some(n) => n << 4 | (addr >> 8) as u8 & 0xF,
+                        None => (addr >> 8) as u8,
+                    };
+                }
+                Fixup::UnpackLo => self.rom[idx + 1] = addr as u8,
+            }
+        }
+
+        let mut labels: Vec<(String, u16)> = self.labels.into_iter().collect();
+        labels.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        // :org can emit out of order; later writes to an address win
+        let mut lines: Vec<(u16, usize)> = Vec::new();
+        for (addr, line) in self.lines {
+            lines.retain(|(a, _)| *a != addr);
+            lines.push((addr, line));
+        }
+        lines.sort_by_key(|(addr, _)| *addr);
+        Ok(Program {
+            rom: self.rom,
+            labels,
+            breakpoints: self.breakpoints,
+            lines,
+        })
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), OctoError> {
+        let end = self.here as usize + bytes.len();
+        if end > self.platform.ram_size() {
+            return Err(self.last.error("program does not fit in memory"));
+        }
+        let addr = self.here as u16;
+        let same_line = self.lines.last().map(|(_, line)| *line) == Some(self.line);
+        if !same_line || self.line_end != self.here {
+            self.lines.push((addr, self.line));
+        }
+        self.line_end = end as u32;
+        let idx = (addr - START_ADDR) as usize;
+        if self.rom.len() < idx + bytes.len() {
+            self.rom.resize(idx + bytes.len(), 0);
+        }
+        self.rom[idx..idx + bytes.len()].copy_from_slice(bytes);
+        self.here = end as u32;
+        Ok(())
+    }
+
+    fn op(&mut self, op: u16) -> Result<(), OctoError> {
+        self.emit(&op.to_be_bytes())
+    }
+
+    fn op_x(&mut self, base: u16) -> Result<(), OctoError> {
+        let x = self.register()?;
+        self.op(base | (x as u16) << 8)
+    }
+
+    // opcode with a 12-bit address taken from the next token
+    fn op_addr(&mut self, base: u16) -> Result<(), OctoError> {
+        let token = self.next()?;
+        match self.address(&token)? {
+            Some(addr) if addr > 0xFFF => Err(token.error(format!("{:#X} is past 0xFFF", addr))),
+            Some(addr) => self.op(base | addr),
+            None => {
+                self.fixups.push((self.here as u16, token, Fixup::Addr12));
+                self.op(base)
+            }
+        }
+    }
+
+    fn patch_jump(&mut self, at: u16, token: &Token) -> Result<(), OctoError> {
+        if self.here > 0xFFF {
+            return Err(token.error("jump target is past 0xFFF"));
+        }
+        let op = 0x1000 | self.here as u16;
+        let idx = (at - START_ADDR) as usize;
+        self.rom[idx..idx + 2].copy_from_slice(&op.to_be_bytes());
+        Ok(())
+    }
+
+    fn require_schip(&self, token: &Token) -> Result<(), OctoError> {
+        if !self.platform.supports_schip() {
+            return Err(token.error(format!(
+                "{} needs a SUPER-CHIP or XO-CHIP target",
+                token.text
+            )));
+        }
+        Ok(())
+    }
+
+    fn require_xo(&self, token: &Token) -> Result<(), OctoError> {
+        if !self.platform.supports_xo() {
+            return Err(token.error(format!("{} needs an XO-CHIP target", token.text)));
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<Token, OctoError> {
+        let token = self.next()?;
+        if parse_number(&token.text).is_some()
+            || token.text.starts_with([':', '"', '{', '}'])
+            || self.reg(&token.text).is_some()
+        {
+            return Err(token.error(format!("'{}' is not a valid name", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn define_label(&mut self, name: &Token, addr: u32) -> Result<(), OctoError> {
+        if self.labels.contains_key(&name.text) || self.consts.contains_key(&name.text) {
+            return Err(name.error(format!("'{}' is already defined", name.text)));
+        }
+        if name.text == "main"
+            && self.main_jump
+            && self.here == START_ADDR as u32 + 2
+            && self.labels.is_empty()
+        {
+            self.rom.clear();
+            self.fixups.remove(0);
+            self.here = START_ADDR as u32;
+            self.main_jump = false;
+            return self.define_label(name, self.here);
+        }
+        if addr > 0xFFFF {
+            return Err(name.error("label is past the end of memory"));
+        }
+        self.labels.insert(name.text.clone(), addr as u16);
+        Ok(())
+    }
+
+    fn define_const(&mut self, name: &Token, value: f64) -> Result<(), OctoError> {
+        if self.labels.contains_key(&name.text) {
+            return Err(name.error(format!("'{}' is already a label", name.text)));
+        }
+        self.consts.insert(name.text.clone(), value);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), OctoError> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        while !self.peek_is("{") {
+            params.push(self.name()?.text);
+        }
+        self.expect("{")?;
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+        self.macros.insert(
+            name.text,
+            Macro {
+                params,
+                body,
+                calls: 0,
+            },
+        );
+        Ok(())
+    }
+
+    fn expand(&mut self, call: &Token) -> Result<(), OctoError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(call.error("too many macro expansions, is a macro recursive?"));
+        }
+        let mac = self.macros[&call.text].clone();
+        let mut args = HashMap::new();
+        for param in &mac.params {
+            args.insert(param.clone(), self.next()?.text);
+        }
+        for token in mac.body.iter().rev() {
+            let text = match token.text.as_str() {
+                "CALLS" => mac.calls.to_string(),
+                text => args.get(text).cloned().unwrap_or_else(|| text.to_string()),
+            };
+            // expanded code maps to the line that invoked the macro
+            self.tokens.push_front(Token {
+                text,
+                line: call.line,
+                column: call.column,
+            });
+        }
+        self.macros.get_mut(&call.text).unwrap().calls += 1;
+        Ok(())
+    }
+
+    fn unpack(&mut self) -> Result<(), OctoError> {
+        let kind = self.next()?;
+        let nibble = if kind.text == "long" {
+            None
+        } else {
+            let value = self.int(&kind)?;
+            if !(0..=0xF).contains(&value) {
+                return Err(kind.error("unpack prefix must be a nibble"));
+            }
+            Some(value as u8)
+        };
+        let target = self.next()?;
+        match self.address(&target)? {
+            Some(addr) => {
+                let hi = match nibble {
+                    Some(n) => n << 4 | (addr >> 8) as u8 & 0xF,
+                    None => (addr >> 8) as u8,
+                };
+                self.op(0x6000 | hi as u16)?;
+                self.op(0x6100 | addr & 0xFF)
+            }
+            None => {
+                self.fixups
+                    .push((self.here as u16, target.clone(), Fixup::UnpackHi(nibble)));
+                self.op(0x6000)?;
+                self.fixups
+                    .push((self.here as u16, target, Fixup::UnpackLo));
+                self.op(0x6100)
+            }
+        }
+    }
+
+    fn assign_i(&mut self) -> Result<(), OctoError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" => {}
+            "+=" => return self.op_x(0xF01E),
+            _ => return Err(op.error("expected := or += after i")),
+        }
+        let token = self.next()?;
+        match token.text.as_str() {
+            "hex" => self.op_x(0xF029),
+            "bighex" => {
+                self.require_schip(&token)?;
+                self.op_x(0xF030)
+            }
+            "long" => {
+                self.require_xo(&token)?;
+                let target = self.next()?;
+                let addr = match self.address(&target)? {
+                    Some(addr) => addr,
+                    None => {
+                        self.fixups
+                            .push((self.here as u16 + 2, target, Fixup::Addr16));
+                        0
+                    }
+                };
+                self.op(0xF000)?;
+                self.op(addr)
+            }
+            _ => {
+                self.tokens.push_front(token);
+                self.op_addr(0xA000)
+            }
+        }
+    }
+
+    fn assign_register(&mut self, x: u8) -> Result<(), OctoError> {
+        let op = self.next()?;
+        let x = (x as u16) << 8;
+        let rhs = self.next()?;
+        let reg_only = |base: u16, rhs: Operand| match rhs {
+            Operand::Reg(y) => Ok(base | x | (y as u16) << 4),
+            Operand::Byte(_) => Err(rhs_error(&op)),
+        };
+        let opcode = match op.text.as_str() {
+            ":=" => match rhs.text.as_str() {
+                "random" => {
+                    let mask = self.next()?;
+                    let mask = self.int(&mask).and_then(|v| byte_in_range(&mask, v))?;
+                    0xC000 | x | mask as u16
+                }
+                "key" => 0xF00A | x,
+                "delay" => 0xF007 | x,
+                _ => match self.operand(&rhs)? {
+                    Operand::Reg(y) => 0x8000 | x | (y as u16) << 4,
+                    Operand::Byte(n) => 0x6000 | x | n as u16,
+                },
+            },
+            "+=" => match self.operand(&rhs)? {
+                Operand::Reg(y) => 0x8004 | x | (y as u16) << 4,
+                Operand::Byte(n) => 0x7000 | x | n as u16,
+            },
+            "-=" => match self.operand(&rhs)? {
+                Operand::Reg(y) => 0x8005 | x | (y as u16) << 4,
+                Operand::Byte(n) => 0x7000 | x | n.wrapping_neg() as u16,
+            },
+            "=-" => reg_only(0x8007, self.operand(&rhs)?)?,
+            "|=" => reg_only(0x8001, self.operand(&rhs)?)?,
+            "&=" => reg_only(0x8002, self.operand(&rhs)?)?,
+            "^=" => reg_only(0x8003, self.operand(&rhs)?)?,
+            ">>=" => reg_only(0x8006, self.operand(&rhs)?)?,
+            "<<=" => reg_only(0x800E, self.operand(&rhs)?)?,
+            _ => return Err(op.error(format!("unknown operator '{}'", op.text))),
+        };
+        self.op(opcode)
+    }
+
+    fn if_statement(&mut self) -> Result<(), OctoError> {
+        let start = self.last.clone();
+        // peek at the keyword after the condition to know which way to skip
+        let keyword = self
+            .tokens
+            .iter()
+            .position(|t| t.text == "then" || t.text == "begin")
+            .map(|idx| self.tokens[idx].text.clone())
+            .ok_or_else(|| start.error("if without then or begin"))?;
+        if keyword == "then" {
+            self.condition(false)?;
+            self.expect("then")?;
+        } else {
+            self.condition(true)?;
+            self.expect("begin")?;
+            let at = self.here as u16;
+            self.op(0x1000)?;
+            self.branches.push((start, at));
+        }
+        Ok(())
+    }
+
+    // emits code that skips the next instruction when the condition equals `when`
+    fn condition(&mut self, when: bool) -> Result<(), OctoError> {
+        let x = self.register()?;
+        let cmp = self.next()?;
+        let xs = (x as u16) << 8;
+        match cmp.text.as_str() {
+            "key" | "-key" => {
+                let skp = (cmp.text == "key") == when;
+                return self.op((if skp { 0xE09E } else { 0xE0A1 }) | xs);
+            }
+            "==" | "!=" => {
+                let rhs = self.next()?;
+                let equal = (cmp.text == "==") == when;
+                let opcode = match self.operand(&rhs)? {
+                    Operand::Byte(n) => (if equal { 0x3000 } else { 0x4000 }) | xs | n as u16,
+                    Operand::Reg(y) => (if equal { 0x5000 } else { 0x9000 }) | xs | (y as u16) << 4,
+                };
+                return self.op(opcode);
+            }
+            "<" | ">" | "<=" | ">=" => {}
+            _ => return Err(cmp.error(format!("unknown comparison '{}'", cmp.text))),
+        }
+        if x == 0xF {
+            return Err(cmp.error("vf cannot be used with <, >, <= or >="));
+        }
+        let rhs = self.next()?;
+        match self.operand(&rhs)? {
+            Operand::Byte(n) => self.op(0x6F00 | n as u16)?,
+            Operand::Reg(y) => self.op(0x8F00 | (y as u16) << 4)?,
+        }
+        // vf := rhs - vx for < and >=, vx - rhs otherwise, leaving vf = 1 without borrow
+        let (sub, holds_when_set) = match cmp.text.as_str() {
+            "<" => (0x8F07, false),
+            ">=" => (0x8F07, true),
+            ">" => (0x8F05, false),
+            _ => (0x8F05, true),
+        };
+        self.op(sub | (x as u16) << 4)?;
+        self.op(if holds_when_set == when {
+            0x3F01
+        } else {
+            0x3F00
+        })
+    }
+
+    fn reg(&self, text: &str) -> Option<u8> {
+        if let Some(reg) = self.aliases.get(text) {
+            return Some(*reg);
+        }
+        let digit = text.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn register(&mut self) -> Result<u8, OctoError> {
+        let token = self.next()?;
+        self.reg(&token.text)
+            .ok_or_else(|| token.error(format!("expected a register, found '{}'", token.text)))
+    }
+
+    fn operand(&self, token: &Token) -> Result<Operand, OctoError> {
+        if let Some(reg) = self.reg(&token.text) {
+            return Ok(Operand::Reg(reg));
+        }
+        let value = self.int(token)?;
+        Ok(Operand::Byte(byte_in_range(token, value)?))
+    }
+
+    fn nibble(&mut self) -> Result<u16, OctoError> {
+        let token = self.next()?;
+        let value = self.int(&token)?;
+        if !(0..=0xF).contains(&value) {
+            return Err(token.error(format!("{} does not fit in a nibble", value)));
+        }
+        Ok(value as u16)
+    }
+
+    fn number(&self, token: &Token) -> Result<f64, OctoError> {
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(value as f64);
+        }
+        if let Some(value) = self.consts.get(&token.text) {
+            return Ok(*value);
+        }
+        if let Some(addr) = self.labels.get(&token.text) {
+            return Ok(*addr as f64);
+        }
+        Err(token.error(format!("expected a number, found '{}'", token.text)))
+    }
+
+    fn int(&self, token: &Token) -> Result<i64, OctoError> {
+        Ok(self.number(token)?.floor() as i64)
+    }
+
+    // None for names that are not defined yet
+    fn address(&self, token: &Token) -> Result<Option<u16>, OctoError> {
+        if token.text.starts_with([':', '"', '{', '}']) || self.reg(&token.text).is_some() {
+            return Err(token.error(format!("expected an address, found '{}'", token.text)));
+        }
+        let value = match self.number(token) {
+            Ok(value) => value.floor() as i64,
+            Err(_) if parse_number(&token.text).is_none() => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if !(0..=0xFFFF).contains(&value) {
+            return Err(token.error(format!("{} is not a valid address", value)));
+        }
+        Ok(Some(value as u16))
+    }
+
+    fn calc(&mut self) -> Result<f64, OctoError> {
+        let open = self.expect("{")?;
+        let mut body = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "}" {
+                break;
+            }
+            body.push(token);
+        }
+        let mut pos = 0;
+        let value = self.calc_expr(&body, &mut pos, &open)?;
+        if let Some(token) = body.get(pos) {
+            return Err(token.error(format!("unexpected '{}' in expression", token.text)));
+        }
+        Ok(value)
+    }
+
+    // Octo evaluates right to left with no precedence; use parentheses to group
+    fn calc_expr(&self, body: &[Token], pos: &mut usize, open: &Token) -> Result<f64, OctoError> {
+        let lhs = self.calc_term(body, pos, open)?;
+        let op = match body.get(*pos) {
+            Some(op) if op.text != ")" => op.clone(),
+            _ => return Ok(lhs),
+        };
+        *pos += 1;
+        let rhs = self.calc_expr(body, pos, open)?;
+        let (a, b) = (lhs.floor() as i64, rhs.floor() as i64);
+        let bool = |b: bool| if b { 1.0 } else { 0.0 };
+        Ok(match op.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+            ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+            "<" => bool(lhs < rhs),
+            "<=" => bool(lhs <= rhs),
+            ">" => bool(lhs > rhs),
+            ">=" => bool(lhs >= rhs),
+            "==" => bool(lhs == rhs),
+            "!=" => bool(lhs != rhs),
+            _ => return Err(op.error(format!("unknown operator '{}'", op.text))),
+        })
+    }
+
+    fn calc_term(&self, body: &[Token], pos: &mut usize, open: &Token) -> Result<f64, OctoError> {
+        let token = body
+            .get(*pos)
+            .ok_or_else(|| open.error("expression ends early"))?;
+        *pos += 1;
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|v| -v),
+            "~" => Some(|v| !(v.floor() as i64) as f64),
+            "!" => Some(|v| if v == 0.0 { 1.0 } else { 0.0 }),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(f) = unary {
+            return Ok(f(self.calc_term(body, pos, open)?));
+        }
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expr(body, pos, open)?;
+                match body.get(*pos) {
+                    Some(close) if close.text == ")" => *pos += 1,
+                    _ => return Err(token.error("unbalanced parenthesis")),
+                }
+                Ok(value)
+            }
+            "@" => {
+                let addr = self.calc_term(body, pos, open)?.floor() as i64;
+                let idx = addr - START_ADDR as i64;
+                Ok(self
+                    .rom
+                    .get(idx as usize)
+                    .filter(|_| idx >= 0)
+                    .copied()
+                    .unwrap_or(0) as f64)
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            text => match text.parse::<f64>() {
+                Ok(value) => Ok(value),
+                Err(_) => self.number(token),
+            },
+        }
+    }
+}
+
+fn byte_in_range(token: &Token, value: i64) -> Result<u8, OctoError> {
+    if !(-128..=255).contains(&value) {
+        return Err(token.error(format!("{} does not fit in a byte", value)));
+    }
+    Ok(value as u8)
+}
+
+fn rhs_error(op: &Token) -> OctoError {
+    op.error(format!("{} needs a register on the right", op.text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Emu, Quirks};
+
+    fn rom(source: &str) -> Vec<u8> {
+        compile(source, Platform::XoChip).unwrap().rom
+    }
+
+    fn run(source: &str) -> Emu {
+        let mut emu = Emu::with_platform(Platform::XoChip, Quirks::xo_chip());
+        emu.load(&rom(source)).unwrap();
+        for _ in 0..1000 {
+            emu.tick().unwrap();
+        }
+        emu
+    }
+
+    #[test]
+    fn test_main_placement() {
+        assert_eq!(rom(": main v0 := 5"), vec![0x60, 0x05]);
+        assert_eq!(
+            rom(": sub return\n: main sub"),
+            vec![0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]
+        );
+        let err = compile(": start clear", Platform::Chip8).unwrap_err();
+        assert_eq!(err.message, "program has no main label");
+    }
+
+    #[test]
+    fn test_instructions() {
+        assert_eq!(
+            rom(": main clear v3 += -1 v1 -= v2 i := hex v3 sprite v0 v1 5 i := long data : data"),
+            vec![
+                0x00, 0xE0, 0x73, 0xFF, 0x81, 0x25, 0xF3, 0x29, 0xD0, 0x15, 0xF0, 0x00, 0x02, 0x0E
+            ]
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let emu = run(": main
+            v0 := 0
+            v1 := 0
+            loop
+              v0 += 1
+              if v0 == 3 then v1 += 10
+              if v0 > 5 begin
+                v2 := 1
+              else
+                v2 := 2
+              end
+              while v0 < 8
+            again
+            : halt jump halt");
+        assert_eq!(emu.get_v_reg()[0], 8);
+        assert_eq!(emu.get_v_reg()[1], 10);
+        assert_eq!(emu.get_v_reg()[2], 1);
+    }
+
+    #[test]
+    fn test_macros_and_constants() {
+        let source = ":const START 4
+            :calc DOUBLE { START * 2 }
+            :alias counter v5
+            :macro bump reg amount { reg += amount }
+            : main
+            counter := DOUBLE
+            bump counter START
+            bump v6 { 1 }";
+        let err = compile(source, Platform::Chip8).unwrap_err();
+        assert_eq!((err.line, err.column), (8, 13));
+
+        let source = source.replace("{ 1 }", "1");
+        assert_eq!(
+            compile(&source, Platform::Chip8).unwrap().rom,
+            vec![0x65, 0x08, 0x75, 0x04, 0x76, 0x01]
+        );
+        assert_eq!(rom(":calc X { 1 + 2 * 3 - 1 } : main X"), vec![5]);
+    }
+
+    #[test]
+    fn test_unpack_and_next() {
+        let emu = run(": main
+            :unpack 0xA target
+            :unpack long target
+            : set-v3 :next patched v3 := 0
+            i := patched
+            v4 := 7
+            save v4 - v4
+            jump set-v3
+            : target 1 2");
+        assert_eq!(emu.get_v_reg()[0], 0x02);
+        assert_eq!(emu.get_v_reg()[1], 0x12);
+        assert_eq!(emu.get_v_reg()[3], 7);
+    }
+
+    #[test]
+    fn test_targets() {
+        let err = compile(": main hires", Platform::Chip8).unwrap_err();
+        assert_eq!(err.message, "hires needs a SUPER-CHIP or XO-CHIP target");
+        assert!(compile(": main hires", Platform::SuperChip).is_ok());
+        assert!(compile(": main plane 3", Platform::SuperChip).is_err());
+        assert!(compile(": main plane 3", Platform::XoChip).is_ok());
+    }
+
+    #[test]
+    fn test_line_map() {
+        let program = compile(
+            "# demo\n: main\n  v0 := 1\n  v1 := 2 v2 := 3\n: data\n  0xFF 0x00\n",
+            Platform::Chip8,
+        )
+        .unwrap();
+        assert_eq!(program.line_at(0x200), Some(3));
+        assert_eq!(program.line_at(0x202), Some(4));
+        assert_eq!(program.line_at(0x204), Some(4));
+        assert_eq!(program.line_at(0x207), Some(6));
+        assert_eq!(program.line_at(0x208), None);
+        assert!(program.labels.contains(&("data".to_string(), 0x206)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = compile(": main\n  jump nowhere\n", Platform::Chip8).unwrap_err();
+        assert_eq!(
+            (err.line, err.column, err.message.as_str()),
+            (2, 8, "undefined name 'nowhere'")
+        );
+        let err = compile(": main\n  loop\n", Platform::Chip8).unwrap_err();
+        assert_eq!((err.line, err.column), (2, 3));
+        let err = compile(": main\n  v0 := 300\n", Platform::Chip8).unwrap_err();
+        assert_eq!((err.line, err.column), (2, 9));
+    }
+}
@@ -0,0 +1,58 @@
+use crate::Quirks;
+
+/// The instruction set an `Emu` runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    /// The quirks ROMs written for this platform usually expect.
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::modern(),
+            Platform::SuperChip => Quirks::schip(),
+            Platform::XoChip => Quirks::xo_chip(),
+        }
+    }
+
+    /// Bytes of addressable memory.
+    pub fn ram_size(self) -> usize {
+        match self {
+            Platform::XoChip => 0x10000,
+            _ => 0x1000,
+        }
+    }
+
+    /// Whether the SUPER-CHIP 1.1 opcodes are available.
+    pub fn supports_schip(self) -> bool {
+        self != Platform::Chip8
+    }
+
+    /// Whether the XO-CHIP opcodes are available.
+    pub fn supports_xo(self) -> bool {
+        self == Platform::XoChip
+    }
+
+    /// The name `from_name` accepts for this platform.
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xo",
+        }
+    }
+
+    /// Looks up a platform by name: `chip8`, `schip` or `xo`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chip8" => Some(Platform::Chip8),
+            "schip" | "superchip" => Some(Platform::SuperChip),
+            "xo" | "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+/// Behaviour switches for opcodes that differ between CHIP-8 interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place instead of loading VY first.
+    pub shift: bool,
+    /// FX55/FX65 leave I pointing past the last register touched.
+    pub memory_increment: bool,
+    /// BNNN jumps to VX + NNN (BXNN) instead of V0 + NNN.
+    pub jump: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to zero.
+    pub vf_reset: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clipping: bool,
+    /// DXYN waits for the next frame, allowing at most one draw per frame.
+    pub display_wait: bool,
+    /// FX0A takes a key as soon as it is held instead of waiting for a
+    /// press and release, as some early emulators did.
+    pub key_wait_held: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub const fn cosmac_vip() -> Self {
+        Self {
+            shift: false,
+            memory_increment: true,
+            jump: false,
+            vf_reset: true,
+            clipping: true,
+            display_wait: true,
+            key_wait_held: false,
+        }
+    }
+
+    /// CHIP-48 and SUPER-CHIP on the HP48 calculators.
+    pub const fn schip() -> Self {
+        Self {
+            shift: true,
+            memory_increment: false,
+            jump: true,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+            key_wait_held: false,
+        }
+    }
+
+    /// Octo's XO-CHIP interpreter.
+    pub const fn xo_chip() -> Self {
+        Self {
+            shift: false,
+            memory_increment: true,
+            jump: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+            key_wait_held: false,
+        }
+    }
+
+    /// What most modern emulators and ROMs expect.
+    pub const fn modern() -> Self {
+        Self {
+            shift: true,
+            memory_increment: false,
+            jump: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+            key_wait_held: false,
+        }
+    }
+
+    /// Looks up a preset by name: `vip`, `schip`, `xo` or `modern`.
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "vip" | "cosmac" | "chip8" => Some(Self::cosmac_vip()),
+            "schip" | "chip48" | "superchip" => Some(Self::schip()),
+            "xo" | "xochip" => Some(Self::xo_chip()),
+            "modern" => Some(Self::modern()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::modern()
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+
+use crate::Emu;
+
+// Only the newest snapshot is kept whole. Every older one is stored as a delta
+// that turns its successor back into it: the XOR of the two states with runs of
+// unchanged bytes collapsed, which is tiny since most of RAM never changes
+// between frames.
+const DELTA_FULL: u8 = 0;
+const DELTA_XOR: u8 = 1;
+
+/// Bounded history of per-frame save states for stepping a game backwards.
+#[derive(Debug, Clone)]
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    used: usize,
+}
+
+impl RewindBuffer {
+    /// Creates a buffer that holds at most `capacity` bytes of history.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+            used: 0,
+        }
+    }
+
+    /// Records the current state of `emu` as the newest frame.
+    pub fn push(&mut self, emu: &Emu) {
+        let state = emu.save_state();
+        if let Some(previous) = self.latest.take() {
+            let delta = encode_delta(&state, &previous);
+            self.used += delta.len();
+            self.used -= previous.len();
+            self.deltas.push_back(delta);
+        }
+        self.used += state.len();
+        self.latest = Some(state);
+
+        while self.used > self.capacity {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Restores the frame before the newest one into `emu`, dropping the newest.
+    /// Returns false once the history is used up.
+    pub fn rewind(&mut self, emu: &mut Emu) -> bool {
+        let (latest, delta) = match (self.latest.take(), self.deltas.pop_back()) {
+            (Some(latest), Some(delta)) => (latest, delta),
+            (latest, _) => {
+                self.latest = latest;
+                return false;
+            }
+        };
+        let previous = apply_delta(&latest, &delta);
+        self.used -= latest.len() + delta.len();
+        self.used += previous.len();
+        let restored = emu.load_state(&previous).is_ok();
+        self.latest = Some(previous);
+        restored
+    }
+
+    /// Number of frames that can still be rewound.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Bytes currently held, never more than the capacity once two frames are stored.
+    pub fn memory_used(&self) -> usize {
+        self.used
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.used = 0;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push(val as u8 | 0x80);
+        val >>= 7;
+    }
+    out.push(val as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        val |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return val;
+        }
+        shift += 7;
+    }
+}
+
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    if from.len() != to.len() {
+        let mut delta = vec![DELTA_FULL];
+        delta.extend_from_slice(to);
+        return delta;
+    }
+    let mut delta = vec![DELTA_XOR];
+    let mut pos = 0;
+    while pos < from.len() {
+        let start = pos;
+        while pos < from.len() && from[pos] == to[pos] {
+            pos += 1;
+        }
+        let unchanged = pos - start;
+        let start = pos;
+        while pos < from.len() && from[pos] != to[pos] {
+            pos += 1;
+        }
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, pos - start);
+        delta.extend(from[start..pos].iter().zip(&to[start..pos]).map(|(a, b)| a ^ b));
+    }
+    delta
+}
+
+fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    if delta[0] == DELTA_FULL {
+        return delta[1..].to_vec();
+    }
+    let mut to = from.to_vec();
+    let mut pos = 1;
+    let mut addr = 0;
+    while pos < delta.len() {
+        addr += read_varint(delta, &mut pos);
+        let changed = read_varint(delta, &mut pos);
+        for byte in &mut to[addr..addr + changed] {
+            *byte ^= delta[pos];
+            pos += 1;
+        }
+        addr += changed;
+    }
+    to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter_emu() -> Emu {
+        let mut emu = Emu::new();
+        // V0 += 1, jump back
+        emu.load(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        emu
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let a = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        let b = vec![1, 2, 9, 4, 5, 6, 0, 0];
+        let delta = encode_delta(&a, &b);
+        assert_eq!(apply_delta(&a, &delta), b);
+        let c = vec![1, 2];
+        assert_eq!(apply_delta(&a, &encode_delta(&a, &c)), c);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut emu = counter_emu();
+        let mut buffer = RewindBuffer::new(1 << 20);
+        for _ in 0..10 {
+            emu.tick().unwrap();
+            emu.tick().unwrap();
+            buffer.push(&emu);
+        }
+        assert_eq!(emu.get_v_reg()[0], 10);
+        assert_eq!(buffer.len(), 9);
+        assert!(buffer.rewind(&mut emu));
+        assert_eq!(emu.get_v_reg()[0], 9);
+        assert!(buffer.rewind(&mut emu));
+        assert_eq!(emu.get_v_reg()[0], 8);
+        while buffer.rewind(&mut emu) {}
+        assert_eq!(emu.get_v_reg()[0], 1);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut emu = counter_emu();
+        let full = emu.save_state().len();
+        let mut buffer = RewindBuffer::new(full + 200);
+        for _ in 0..100 {
+            emu.tick().unwrap();
+            emu.tick().unwrap();
+            buffer.push(&emu);
+        }
+        assert!(buffer.memory_used() <= full + 200);
+        assert!(buffer.len() > 1 && buffer.len() < 99);
+    }
+}
@@ -0,0 +1,65 @@
+/// A seed for runs that don't name one. Frontends use it to pick the seed a
+/// new movie records, so every frontend seeds the same way.
+pub fn random_seed() -> u64 {
+    rand::random()
+}
+
+/// xorshift64* generator behind CXNN. Small, fast and fully described by one
+/// `u64`, so it can be seeded for reproducible runs and stored in save states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // splitmix64 spreads similar seeds apart and never yields the all-zero state
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self::from_state(z)
+    }
+
+    pub(crate) fn from_state(state: u64) -> Self {
+        Self {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub(crate) fn next_u8(&mut self) -> u8 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let a: Vec<u8> = (0..32).map(|_| a.next_u8()).collect();
+        let b: Vec<u8> = (0..32).map(|_| b.next_u8()).collect();
+        assert_eq!(a, b);
+        let mut c = Rng::new(43);
+        let c: Vec<u8> = (0..32).map(|_| c.next_u8()).collect();
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_zero_state() {
+        let mut rng = Rng::from_state(0);
+        assert_ne!(rng.next_u8() as u64 | rng.state(), 0);
+    }
+}
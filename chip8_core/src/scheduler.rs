@@ -0,0 +1,161 @@
+use crate::{Emu, EmuError};
+use std::time::Duration;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+const TIMER_HZ: u128 = 60;
+
+/// Runs an `Emu` in step with real time: `ips` instructions per second and
+/// timers at exactly 60 Hz, interleaved in the order they fall due, however
+/// often the frontend calls `advance`.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    ips: u32,
+    max_lag: Duration,
+    // time fed in and work done since the rate last changed
+    elapsed_ns: u128,
+    instructions: u128,
+    timer_ticks: u128,
+}
+
+impl Scheduler {
+    /// 20 instructions per 60 Hz frame, which most ROMs are tuned for.
+    pub const DEFAULT_IPS: u32 = 1200;
+    /// Longest stretch `advance` catches up on, so a stalled frontend
+    /// doesn't come back to a burst of fast-forwarded frames.
+    pub const DEFAULT_MAX_LAG: Duration = Duration::from_millis(250);
+
+    pub fn new(ips: u32) -> Self {
+        Self {
+            ips,
+            max_lag: Self::DEFAULT_MAX_LAG,
+            elapsed_ns: 0,
+            instructions: 0,
+            timer_ticks: 0,
+        }
+    }
+
+    pub fn ips(&self) -> u32 {
+        self.ips
+    }
+
+    pub fn set_ips(&mut self, ips: u32) {
+        self.ips = ips;
+        self.rebase();
+    }
+
+    pub fn set_max_lag(&mut self, max_lag: Duration) {
+        self.max_lag = max_lag;
+    }
+
+    /// Forgets time owed but not yet run, e.g. after pausing or rewinding.
+    pub fn rebase(&mut self) {
+        self.elapsed_ns = 0;
+        self.instructions = 0;
+        self.timer_ticks = 0;
+    }
+
+    /// Runs everything that fell due during `elapsed` and returns how many
+    /// 60 Hz timer ticks happened, i.e. how many emulated frames passed.
+    pub fn advance(&mut self, emu: &mut Emu, elapsed: Duration) -> Result<u32, EmuError> {
+        self.elapsed_ns += elapsed.min(self.max_lag).as_nanos();
+        let ips = self.ips as u128;
+        let due_instructions = self.elapsed_ns * ips / NANOS_PER_SEC;
+        let due_ticks = self.elapsed_ns * TIMER_HZ / NANOS_PER_SEC;
+
+        let mut frames = 0;
+        loop {
+            let instruction_due = self.instructions < due_instructions;
+            let tick_due = self.timer_ticks < due_ticks;
+            // instruction n + 1 runs at (n + 1) / ips seconds and tick m + 1 at
+            // (m + 1) / 60; on a tie the instruction goes first
+            let instruction_first = instruction_due
+                && (!tick_due
+                    || (self.instructions + 1) * TIMER_HZ <= (self.timer_ticks + 1) * ips);
+            if instruction_first {
+                self.instructions += 1;
+                emu.tick()?;
+            } else if tick_due {
+                self.timer_ticks += 1;
+                emu.tick_timers();
+                frames += 1;
+            } else {
+                break;
+            }
+        }
+        Ok(frames)
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_IPS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ADD V0, 1; LD V1, DT; JP 0x200
+    const COUNTER: [u8; 6] = [0x70, 0x01, 0xF1, 0x07, 0x12, 0x00];
+
+    fn counter_emu() -> Emu {
+        let mut emu = Emu::new();
+        emu.load(&COUNTER).unwrap();
+        emu.set_dt(100);
+        emu
+    }
+
+    #[test]
+    fn test_runs_at_ips_and_60hz() {
+        let mut emu = counter_emu();
+        let mut sched = Scheduler::new(600);
+        // a 144 Hz display calling in every ~6.9 ms for one second
+        let mut frames = 0;
+        for _ in 0..144 {
+            frames += sched
+                .advance(&mut emu, Duration::from_nanos(1_000_000_000 / 144 + 1))
+                .unwrap();
+        }
+        assert_eq!(frames, 60);
+        assert_eq!(emu.get_dt(), 40);
+        // 600 instructions, a third of them ADD
+        assert_eq!(emu.get_v_reg()[0], 200);
+    }
+
+    #[test]
+    fn test_interleaves_timers_with_instructions() {
+        let mut emu = counter_emu();
+        let mut sched = Scheduler::new(60 * 3);
+        // one frame is exactly one loop: ADD, LD V1 DT, JP, then the timer
+        sched
+            .advance(&mut emu, Duration::from_millis(1000 / 60 + 1))
+            .unwrap();
+        assert_eq!(emu.get_v_reg()[1], 100);
+        assert_eq!(emu.get_dt(), 99);
+        sched.advance(&mut emu, Duration::from_millis(17)).unwrap();
+        assert_eq!(emu.get_v_reg()[1], 99);
+        assert_eq!(emu.get_dt(), 98);
+    }
+
+    #[test]
+    fn test_lag_is_capped() {
+        let mut emu = counter_emu();
+        let mut sched = Scheduler::default();
+        sched.set_max_lag(Duration::from_millis(100));
+        let frames = sched.advance(&mut emu, Duration::from_secs(5)).unwrap();
+        assert_eq!(frames, 6);
+        assert_eq!(emu.get_dt(), 94);
+    }
+
+    #[test]
+    fn test_fault_stops_early() {
+        let mut emu = Emu::new();
+        emu.load(&[0x00, 0xEE]).unwrap();
+        let mut sched = Scheduler::default();
+        assert_eq!(
+            sched.advance(&mut emu, Duration::from_secs(1)),
+            Err(EmuError::StackUnderflow)
+        );
+    }
+}
@@ -0,0 +1,510 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::audio::PATTERN_SIZE;
+use crate::rng::Rng;
+use crate::{Emu, KeyWait, Platform, Quirks, HIRES_HEIGHT, HIRES_WIDTH, NUM_KEYS, NUM_PLANES, NUM_REGS};
+use crate::{NUM_RPL_FLAGS, STACK_SIZE};
+
+// A save state is the magic and a format version, then a list of tagged chunks
+// (4 byte tag, u32 length, payload) and a CRC-32 of everything before it.
+// Readers skip chunks they do not know so newer fields can be added without
+// bumping the version.
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 6;
+const CHECKSUM_SIZE: usize = 4;
+
+const PLATFORM: [u8; 4] = *b"PLAT";
+const QUIRKS: [u8; 4] = *b"QRKS";
+const CPU: [u8; 4] = *b"CPU ";
+const RAM: [u8; 4] = *b"RAM ";
+const DISPLAY: [u8; 4] = *b"DISP";
+const KEYS: [u8; 4] = *b"KEYS";
+const MISC: [u8; 4] = *b"MISC";
+const AUDIO: [u8; 4] = *b"AUDI";
+const RNG: [u8; 4] = *b"RNG ";
+const KEY_WAIT: [u8; 4] = *b"KWAI";
+const COUNTERS: [u8; 4] = *b"CNTR";
+
+const SCREEN_SIZE: usize = HIRES_WIDTH * HIRES_HEIGHT;
+
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/// Reasons a save state could not be restored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch,
+    Truncated,
+    MissingChunk([u8; 4]),
+    InvalidChunk([u8; 4]),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::ChecksumMismatch => write!(f, "save state checksum mismatch"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::MissingChunk(tag) => {
+                write!(f, "save state has no {} chunk", String::from_utf8_lossy(tag))
+            }
+            StateError::InvalidChunk(tag) => {
+                write!(f, "save state has an invalid {} chunk", String::from_utf8_lossy(tag))
+            }
+        }
+    }
+}
+
+impl Error for StateError {}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn chunk(&mut self, tag: [u8; 4], data: &[u8]) {
+        self.buf.extend_from_slice(&tag);
+        self.buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        self.buf.extend_from_slice(data);
+    }
+}
+
+struct Reader<'a> {
+    tag: [u8; 4],
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::InvalidChunk(self.tag));
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+fn parse_chunks(data: &[u8]) -> Result<Vec<Chunk<'_>>, StateError> {
+    if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    if data.len() < HEADER_SIZE + CHECKSUM_SIZE {
+        return Err(StateError::Truncated);
+    }
+    let version = u16::from_le_bytes([data[4], data[5]]);
+    if version == 0 || version > VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    let (body, checksum) = data.split_at(data.len() - CHECKSUM_SIZE);
+    let checksum = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    if crc32(body) != checksum {
+        return Err(StateError::ChecksumMismatch);
+    }
+
+    let mut chunks = Vec::new();
+    let mut rest = &body[HEADER_SIZE..];
+    while !rest.is_empty() {
+        if rest.len() < 8 {
+            return Err(StateError::Truncated);
+        }
+        let tag = [rest[0], rest[1], rest[2], rest[3]];
+        let len = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        rest = &rest[8..];
+        if rest.len() < len {
+            return Err(StateError::Truncated);
+        }
+        chunks.push((tag, &rest[..len]));
+        rest = &rest[len..];
+    }
+    Ok(chunks)
+}
+
+fn find<'a>(chunks: &[Chunk<'a>], tag: [u8; 4]) -> Result<Reader<'a>, StateError> {
+    chunks
+        .iter()
+        .find(|(t, _)| *t == tag)
+        .map(|(_, data)| Reader { tag, data })
+        .ok_or(StateError::MissingChunk(tag))
+}
+
+fn pack_bits(pixels: &[bool]) -> Vec<u8> {
+    pixels
+        .chunks(8)
+        .map(|bits| {
+            bits.iter()
+                .enumerate()
+                .fold(0, |byte, (i, bit)| byte | ((*bit as u8) << (7 - i)))
+        })
+        .collect()
+}
+
+fn unpack_bits(bytes: &[u8], pixels: &mut [bool]) {
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        *pixel = bytes[i / 8] & (0x80 >> (i % 8)) != 0;
+    }
+}
+
+fn platform_id(platform: Platform) -> u8 {
+    match platform {
+        Platform::Chip8 => 0,
+        Platform::SuperChip => 1,
+        Platform::XoChip => 2,
+    }
+}
+
+impl Emu {
+    /// Serializes the whole machine so it can be restored with `load_state`.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = Writer { buf: Vec::new() };
+        w.buf.extend_from_slice(MAGIC);
+        w.buf.extend_from_slice(&VERSION.to_le_bytes());
+
+        w.chunk(PLATFORM, &[platform_id(self.platform)]);
+        let q = self.quirks;
+        w.chunk(
+            QUIRKS,
+            &[
+                q.shift as u8,
+                q.memory_increment as u8,
+                q.jump as u8,
+                q.vf_reset as u8,
+                q.clipping as u8,
+                q.display_wait as u8,
+                q.key_wait_held as u8,
+            ],
+        );
+
+        let mut cpu = Vec::new();
+        cpu.extend_from_slice(&self.pc.to_le_bytes());
+        cpu.extend_from_slice(&self.i_reg.to_le_bytes());
+        cpu.extend_from_slice(&self.sp.to_le_bytes());
+        cpu.push(self.dt);
+        cpu.push(self.st);
+        cpu.extend_from_slice(&self.v_reg);
+        for addr in self.stack {
+            cpu.extend_from_slice(&addr.to_le_bytes());
+        }
+        w.chunk(CPU, &cpu);
+
+        w.chunk(RAM, &self.ram);
+
+        let mut display = vec![self.hires as u8, self.planes];
+        for plane in &self.screen {
+            display.extend(pack_bits(plane));
+        }
+        w.chunk(DISPLAY, &display);
+
+        let keys: Vec<u8> = self.keys.iter().map(|k| *k as u8).collect();
+        w.chunk(KEYS, &keys);
+
+        let mut misc = vec![self.vblank_wait as u8, self.exited as u8];
+        misc.extend_from_slice(&self.rpl);
+        w.chunk(MISC, &misc);
+
+        let mut audio = vec![self.pattern.is_some() as u8];
+        audio.extend_from_slice(&self.pattern.unwrap_or_default());
+        audio.push(self.pitch);
+        w.chunk(AUDIO, &audio);
+
+        w.chunk(RNG, &self.rng.state().to_le_bytes());
+
+        let key_wait = match self.key_wait {
+            None => [0, 0, 0],
+            Some(KeyWait::Press { x }) => [1, x, 0],
+            Some(KeyWait::Release { x, key }) => [2, x, key],
+        };
+        w.chunk(KEY_WAIT, &key_wait);
+
+        let mut counters = Vec::new();
+        counters.extend_from_slice(&self.frames.to_le_bytes());
+        counters.extend_from_slice(&self.ticks.to_le_bytes());
+        w.chunk(COUNTERS, &counters);
+
+        let checksum = crc32(&w.buf);
+        w.buf.extend_from_slice(&checksum.to_le_bytes());
+        w.buf
+    }
+
+    /// Restores a state written by `save_state`. On error the machine is left untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let chunks = parse_chunks(data)?;
+
+        let mut r = find(&chunks, PLATFORM)?;
+        let platform = match r.u8()? {
+            0 => Platform::Chip8,
+            1 => Platform::SuperChip,
+            2 => Platform::XoChip,
+            _ => return Err(StateError::InvalidChunk(PLATFORM)),
+        };
+
+        let mut r = find(&chunks, QUIRKS)?;
+        let quirks = Quirks {
+            shift: r.bool()?,
+            memory_increment: r.bool()?,
+            jump: r.bool()?,
+            vf_reset: r.bool()?,
+            clipping: r.bool()?,
+            display_wait: r.bool()?,
+            key_wait_held: r.bool()?,
+        };
+
+        let mut r = find(&chunks, CPU)?;
+        let pc = r.u16()?;
+        let i_reg = r.u16()?;
+        let sp = r.u16()?;
+        let dt = r.u8()?;
+        let st = r.u8()?;
+        let mut v_reg = [0; NUM_REGS];
+        v_reg.copy_from_slice(r.bytes(NUM_REGS)?);
+        let mut stack = [0; STACK_SIZE];
+        for addr in stack.iter_mut() {
+            *addr = r.u16()?;
+        }
+        if sp as usize > STACK_SIZE {
+            return Err(StateError::InvalidChunk(CPU));
+        }
+
+        let r = find(&chunks, RAM)?;
+        if r.data.len() != platform.ram_size() {
+            return Err(StateError::InvalidChunk(RAM));
+        }
+        let ram = r.data.to_vec();
+
+        let mut r = find(&chunks, DISPLAY)?;
+        let hires = r.bool()?;
+        let planes = r.u8()?;
+        if planes as usize >= 1 << NUM_PLANES {
+            return Err(StateError::InvalidChunk(DISPLAY));
+        }
+        let mut screen = [[false; SCREEN_SIZE]; NUM_PLANES];
+        for plane in screen.iter_mut() {
+            unpack_bits(r.bytes(SCREEN_SIZE / 8)?, plane);
+        }
+
+        let mut r = find(&chunks, KEYS)?;
+        let mut keys = [false; NUM_KEYS];
+        for key in keys.iter_mut() {
+            *key = r.bool()?;
+        }
+
+        let mut r = find(&chunks, MISC)?;
+        let vblank_wait = r.bool()?;
+        let exited = r.bool()?;
+        let mut rpl = [0; NUM_RPL_FLAGS];
+        rpl.copy_from_slice(r.bytes(NUM_RPL_FLAGS)?);
+
+        let mut r = find(&chunks, AUDIO)?;
+        let has_pattern = r.bool()?;
+        let mut pattern = [0; PATTERN_SIZE];
+        pattern.copy_from_slice(r.bytes(PATTERN_SIZE)?);
+        let pitch = r.u8()?;
+
+        let rng = Rng::from_state(find(&chunks, RNG)?.u64()?);
+
+        let mut r = find(&chunks, KEY_WAIT)?;
+        let (state, x, key) = (r.u8()?, r.u8()?, r.u8()?);
+        if x as usize >= NUM_REGS || key as usize >= NUM_KEYS {
+            return Err(StateError::InvalidChunk(KEY_WAIT));
+        }
+        let key_wait = match state {
+            0 => None,
+            1 => Some(KeyWait::Press { x }),
+            2 => Some(KeyWait::Release { x, key }),
+            _ => return Err(StateError::InvalidChunk(KEY_WAIT)),
+        };
+
+        let mut r = find(&chunks, COUNTERS)?;
+        let (frames, ticks) = (r.u64()?, r.u64()?);
+
+        self.platform = platform;
+        self.quirks = quirks;
+        self.pc = pc;
+        self.i_reg = i_reg;
+        self.sp = sp;
+        self.dt = dt;
+        self.st = st;
+        self.v_reg = v_reg;
+        self.stack = stack;
+        self.ram = ram;
+        self.hires = hires;
+        self.planes = planes;
+        self.screen = screen;
+        self.dirty.mark_all(self.get_display_size());
+        self.keys = keys;
+        self.key_wait = key_wait;
+        self.vblank_wait = vblank_wait;
+        self.exited = exited;
+        self.rpl = rpl;
+        self.pattern = if has_pattern { Some(pattern) } else { None };
+        self.pitch = pitch;
+        self.rng = rng;
+        self.frames = frames;
+        self.ticks = ticks;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut emu = Emu::with_platform(Platform::XoChip, Quirks::xo_chip());
+        emu.load(&[0x60, 0x2A, 0xA3, 0x00, 0xF0, 0x55, 0xD0, 0x05, 0x12, 0x08]).unwrap();
+        for _ in 0..5 {
+            emu.tick().unwrap();
+        }
+        emu.keypress(3, true).unwrap();
+        let state = emu.save_state();
+
+        let mut restored = Emu::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.platform(), Platform::XoChip);
+        assert_eq!(restored.get_ram()[0x300], 0x2A);
+        assert_eq!(restored.get_display(), emu.get_display());
+    }
+
+    #[test]
+    fn test_rejects_corruption() {
+        let emu = Emu::new();
+        let mut state = emu.save_state();
+        let mut restored = Emu::new();
+
+        assert_eq!(restored.load_state(b"nope"), Err(StateError::BadMagic));
+        assert_eq!(
+            restored.load_state(&state[..state.len() - 1]),
+            Err(StateError::ChecksumMismatch)
+        );
+        state[0x40] ^= 1;
+        assert_eq!(restored.load_state(&state), Err(StateError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn test_rejects_newer_version() {
+        let mut state = Emu::new().save_state();
+        state[4] = 0xFF;
+        assert_eq!(
+            Emu::new().load_state(&state),
+            Err(StateError::UnsupportedVersion(0xFF))
+        );
+    }
+
+    // Rewrites each chunk of `state` with `edit`, dropping the ones it maps
+    // to `None`, and fixes up the checksum.
+    fn rewrite(state: &[u8], edit: impl Fn([u8; 4], &[u8]) -> Option<Vec<u8>>) -> Vec<u8> {
+        let mut w = Writer {
+            buf: state[..HEADER_SIZE].to_vec(),
+        };
+        for (tag, data) in parse_chunks(state).unwrap() {
+            if let Some(data) = edit(tag, data) {
+                w.chunk(tag, &data);
+            }
+        }
+        let checksum = crc32(&w.buf);
+        w.buf.extend_from_slice(&checksum.to_le_bytes());
+        w.buf
+    }
+
+    #[test]
+    fn test_rejects_missing_fields() {
+        let state = Emu::new().save_state();
+        for tag in [RNG, KEY_WAIT, COUNTERS] {
+            let partial = rewrite(&state, |t, data| (t != tag).then(|| data.to_vec()));
+            assert_eq!(
+                Emu::new().load_state(&partial),
+                Err(StateError::MissingChunk(tag))
+            );
+        }
+        let short_quirks = rewrite(&state, |tag, data| {
+            Some(if tag == QUIRKS { &data[..data.len() - 1] } else { data }.to_vec())
+        });
+        assert_eq!(
+            Emu::new().load_state(&short_quirks),
+            Err(StateError::InvalidChunk(QUIRKS))
+        );
+        let bad_planes = rewrite(&state, |tag, data| {
+            let mut data = data.to_vec();
+            if tag == DISPLAY {
+                data[1] = 0b100;
+            }
+            Some(data)
+        });
+        assert_eq!(
+            Emu::new().load_state(&bad_planes),
+            Err(StateError::InvalidChunk(DISPLAY))
+        );
+    }
+    #[test]
+    fn test_restores_key_wait() {
+        let mut emu = Emu::new();
+        emu.load(&[0xF5, 0x0A]).unwrap();
+        emu.tick().unwrap();
+        emu.keypress(0xB, true).unwrap();
+        let state = emu.save_state();
+
+        let mut restored = Emu::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.key_wait(), Some(KeyWait::Release { x: 5, key: 0xB }));
+        restored.keypress(0xB, false).unwrap();
+        assert_eq!(restored.get_v_reg()[5], 0xB);
+    }
+    #[test]
+    fn test_restores_rng() {
+        let rom = [0xC0, 0xFF, 0x12, 0x00];
+        let mut emu = Emu::with_seed(1);
+        emu.load(&rom).unwrap();
+        let state = emu.save_state();
+        emu.tick().unwrap();
+        let first = emu.get_v_reg()[0];
+
+        let mut restored = Emu::with_seed(2);
+        restored.load_state(&state).unwrap();
+        restored.tick().unwrap();
+        assert_eq!(restored.get_v_reg()[0], first);
+    }
+}
@@ -0,0 +1,184 @@
+//! Per-instruction trace log, for diffing runs against other emulators.
+//!
+//! Every executed instruction writes one line:
+//!
+//! ```text
+//! 0200 6012 V=00000000000000000000000000000000 I=0000 SP=0 DT=00 ST=00 -> V=12000000000000000000000000000000 I=0000 SP=0 DT=00 ST=00 ; LD V0, 0x12
+//! ```
+//!
+//! that is the PC and opcode, the registers before and after, then the
+//! mnemonic. All numbers are upper-case hex with fixed widths, so traces
+//! line up column for column. An instruction that faults ends its line
+//! with `-> FAULT <error>` in place of the registers after.
+
+use crate::{decode, Emu, EmuError, NUM_REGS};
+use std::fmt;
+use std::io::{self, Write};
+
+pub(crate) struct Tracer {
+    out: Box<dyn Write + Send>,
+    error: Option<io::Error>,
+}
+
+// the registers a trace line shows, captured before and after each instruction
+pub(crate) struct Registers {
+    v: [u8; NUM_REGS],
+    i: u16,
+    sp: u16,
+    dt: u8,
+    st: u8,
+}
+
+impl Registers {
+    pub(crate) fn capture(emu: &Emu) -> Self {
+        Self {
+            v: emu.v_reg,
+            i: emu.i_reg,
+            sp: emu.sp,
+            dt: emu.dt,
+            st: emu.st,
+        }
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "V=")?;
+        for v in self.v {
+            write!(f, "{:02X}", v)?;
+        }
+        write!(
+            f,
+            " I={:04X} SP={:X} DT={:02X} ST={:02X}",
+            self.i, self.sp, self.dt, self.st
+        )
+    }
+}
+
+impl Tracer {
+    pub(crate) fn new(out: Box<dyn Write + Send>) -> Self {
+        Self { out, error: None }
+    }
+
+    pub(crate) fn log(
+        &mut self,
+        pc: u16,
+        op: u16,
+        before: &Registers,
+        after: Result<&Registers, &EmuError>,
+    ) {
+        // a broken writer shouldn't stop the ROM; the error is reported by `finish`
+        if self.error.is_some() {
+            return;
+        }
+        let result = match after {
+            Ok(after) => writeln!(
+                self.out,
+                "{:04X} {:04X} {} -> {} ; {}",
+                pc,
+                op,
+                before,
+                after,
+                decode(op)
+            ),
+            Err(err) => writeln!(
+                self.out,
+                "{:04X} {:04X} {} -> FAULT {} ; {}",
+                pc,
+                op,
+                before,
+                err,
+                decode(op)
+            ),
+        };
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+
+    /// Flushes the writer, returning the first error hit while tracing.
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.out.flush(),
+        }
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace_lines() {
+        let buf = SharedBuf::default();
+        let mut emu = Emu::new();
+        // LD V0, 0x12; LD I, 0x345; RET with an empty stack
+        emu.load(&[0x60, 0x12, 0xA3, 0x45, 0x00, 0xEE]).unwrap();
+        emu.trace_to(buf.clone());
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert!(emu.tick().is_err());
+        emu.stop_trace().unwrap();
+        emu.tick().ok();
+
+        let text = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let zeros = "0".repeat(32);
+        assert_eq!(
+            lines[0],
+            format!(
+                "0200 6012 V={z} I=0000 SP=0 DT=00 ST=00 -> V=12{} I=0000 SP=0 DT=00 ST=00 ; LD V0, 0x12",
+                &zeros[2..],
+                z = zeros
+            )
+        );
+        assert!(lines[1].starts_with("0202 A345 V=12"));
+        assert!(lines[1].contains("-> V=12") && lines[1].contains("I=0345"));
+        assert!(lines[2].ends_with("-> FAULT stack underflow ; RET"));
+    }
+
+    #[test]
+    fn test_write_error_is_reported() {
+        struct Broken;
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut emu = Emu::new();
+        emu.load(&[0x60, 0x12]).unwrap();
+        emu.trace_to(Broken);
+        emu.tick().unwrap();
+        assert_eq!(emu.get_v_reg()[0], 0x12);
+        assert!(emu.stop_trace().is_err());
+        assert!(emu.stop_trace().is_ok());
+    }
+}
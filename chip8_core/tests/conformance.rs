@@ -0,0 +1,184 @@
+//! Runs the test ROMs in `testroms/conformance` headlessly under each quirk
+//! profile and compares the final screen against the golden PBM images in
+//! `testroms/conformance/golden`, named `<rom>-<profile>.pbm`.
+//!
+//! After an intended change in behaviour, rerun with `UPDATE_GOLDEN=1` to
+//! rewrite the goldens, then review the images before committing them.
+//! When a screen doesn't match, the actual image is written to cargo's
+//! test tmpdir for comparison.
+//!
+//! The checked-in Octo programs' goldens only pin this emulator's output, so
+//! `test_community_suite` also runs Timendus' chip8-test-suite (MIT). Its
+//! ROMs aren't checked in, so the test is ignored: drop the `.ch8` files into
+//! `testroms/conformance` and run it with `cargo test -- --ignored`. A
+//! missing ROM fails it. Bless its goldens only after checking them against
+//! a reference emulator.
+
+use chip8_core::image::Frame;
+use chip8_core::{octo, Emu, Platform, Quirks};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const TICKS_PER_FRAME: usize = 20;
+const SEED: u64 = 0x8;
+const ALL_PROFILES: &[&str] = &["vip", "schip", "xo", "modern"];
+
+struct Case {
+    rom: &'static str,
+    platform: Platform,
+    profiles: &'static [&'static str],
+    frames: usize,
+    // byte stored before running; the community ROMs read a menu choice from 0x1FF
+    poke: Option<(usize, u8)>,
+    // (frame, key, pressed), in frame order
+    keys: &'static [(usize, usize, bool)],
+}
+
+impl Case {
+    const fn new(rom: &'static str, frames: usize) -> Self {
+        Self {
+            rom,
+            platform: Platform::Chip8,
+            profiles: ALL_PROFILES,
+            frames,
+            poke: None,
+            keys: &[],
+        }
+    }
+}
+
+const CASES: &[Case] = &[
+    Case::new("logo.8o", 60),
+    Case::new("opcodes.8o", 60),
+    Case::new("flags.8o", 60),
+    Case::new("quirks.8o", 60),
+    Case {
+        keys: &[
+            (3, 5, true),
+            (6, 5, false),
+            (10, 0xA, true),
+            (14, 0xA, false),
+        ],
+        ..Case::new("keypad.8o", 30)
+    },
+    Case {
+        platform: Platform::SuperChip,
+        profiles: &["schip", "modern"],
+        ..Case::new("hires.8o", 30)
+    },
+];
+
+const COMMUNITY_CASES: &[Case] = &[
+    Case::new("1-chip8-logo.ch8", 30),
+    Case::new("2-ibm-logo.ch8", 30),
+    Case::new("3-corax+.ch8", 60),
+    Case::new("4-flags.ch8", 60),
+    Case {
+        poke: Some((0x1FF, 1)),
+        ..Case::new("5-quirks.ch8", 300)
+    },
+    // the FX0A test: waits for a key, then shows it once it's released
+    Case {
+        poke: Some((0x1FF, 3)),
+        keys: &[(10, 0xA, true), (14, 0xA, false)],
+        ..Case::new("6-keypad.ch8", 30)
+    },
+];
+
+fn rom_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../testroms/conformance")
+}
+
+fn load_rom(path: &Path, platform: Platform) -> Vec<u8> {
+    let bytes = fs::read(path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    if path.extension().is_some_and(|ext| ext == "8o") {
+        let source = String::from_utf8(bytes).unwrap();
+        match octo::compile(&source, platform) {
+            Ok(program) => program.rom,
+            Err(err) => panic!("{}:{}", path.display(), err),
+        }
+    } else {
+        bytes
+    }
+}
+
+fn run(case: &Case, rom: &[u8], profile: &str) -> Result<Frame, String> {
+    let quirks = Quirks::preset(profile).unwrap();
+    let mut emu = Emu::with_platform(case.platform, quirks);
+    emu.reseed(SEED);
+    emu.load(rom).map_err(|err| err.to_string())?;
+    if let Some((addr, val)) = case.poke {
+        emu.write_ram(addr, val).map_err(|err| err.to_string())?;
+    }
+    let mut keys = case.keys.iter().peekable();
+    for frame in 0..case.frames {
+        while let Some((_, key, pressed)) = keys.next_if(|(at, _, _)| *at <= frame) {
+            emu.keypress(*key, *pressed)
+                .map_err(|err| err.to_string())?;
+        }
+        for _ in 0..TICKS_PER_FRAME {
+            emu.tick()
+                .map_err(|err| format!("frame {}: {}", frame, err))?;
+        }
+        emu.tick_timers();
+        if emu.has_exited() {
+            break;
+        }
+    }
+    Ok(Frame::capture(&emu))
+}
+
+// Runs every case under its profiles and returns what didn't match.
+fn check(cases: &[Case]) -> Vec<String> {
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let golden_dir = rom_dir().join("golden");
+    let mut failures = Vec::new();
+    for case in cases {
+        let path = rom_dir().join(case.rom);
+        let rom = load_rom(&path, case.platform);
+        let stem = case.rom.rsplit_once('.').unwrap().0;
+        for profile in case.profiles {
+            let name = format!("{}-{}.pbm", stem, profile);
+            let frame = match run(case, &rom, profile) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    failures.push(format!("{}: {}", name, err));
+                    continue;
+                }
+            };
+            let golden = golden_dir.join(&name);
+            if update {
+                fs::write(&golden, frame.to_pbm()).unwrap();
+                continue;
+            }
+            let expected = fs::read_to_string(&golden)
+                .ok()
+                .and_then(|text| Frame::from_pbm(&text));
+            if expected.as_ref() != Some(&frame) {
+                let actual = Path::new(env!("CARGO_TARGET_TMPDIR")).join(&name);
+                fs::write(&actual, frame.to_pbm()).unwrap();
+                let reason = if expected.is_some() {
+                    "screen differs"
+                } else {
+                    "missing golden"
+                };
+                failures.push(format!("{}: {}, see {}", name, reason, actual.display()));
+            }
+        }
+    }
+    failures
+}
+
+#[test]
+fn test_conformance() {
+    let failures = check(CASES);
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+#[ignore = "needs the chip8-test-suite ROMs in testroms/conformance"]
+fn test_community_suite() {
+    let failures = check(COMMUNITY_CASES);
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,147 @@
+use chip8_core::gdb::GdbStub;
+use chip8_core::{Debugger, Emu};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+// A minimal client speaking just enough of the protocol to drive the stub.
+struct Client {
+    stream: TcpStream,
+    acks: bool,
+}
+
+impl Client {
+    fn send(&mut self, packet: &str) -> String {
+        let sum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", packet, sum).unwrap();
+        if self.acks {
+            assert_eq!(self.byte(), b'+');
+        }
+        self.reply()
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let mut checksum = [0; 2];
+        self.stream.read_exact(&mut checksum).unwrap();
+        let sum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        assert_eq!(
+            std::str::from_utf8(&checksum).unwrap(),
+            format!("{:02x}", sum)
+        );
+        if self.acks {
+            self.stream.write_all(b"+").unwrap();
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+}
+
+fn connect(rom: &[u8]) -> (Client, thread::JoinHandle<Debugger>) {
+    let mut emu = Emu::new();
+    emu.load(rom).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut stub = GdbStub::new(Debugger::new(emu));
+        stub.serve(stream).unwrap();
+        stub.into_debugger()
+    });
+    let client = Client {
+        stream: TcpStream::connect(addr).unwrap(),
+        acks: true,
+    };
+    (client, server)
+}
+
+// 200: LD V0 0x12, 202: LD I 0x300, 204: LD [I] V0, 206: JP 0x206
+const ROM: [u8; 8] = [0x60, 0x12, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06];
+
+#[test]
+fn test_handshake_and_target_xml() {
+    let (mut client, server) = connect(&ROM);
+    let features = client.send("qSupported:multiprocess+;xmlRegisters=i386");
+    assert!(features.contains("qXfer:features:read+"));
+    assert_eq!(client.send("QStartNoAckMode"), "OK");
+    client.acks = false;
+    assert_eq!(client.send("?"), "S05");
+
+    let mut xml = String::new();
+    loop {
+        let chunk = client.send(&format!(
+            "qXfer:features:read:target.xml:{:x},80",
+            xml.len()
+        ));
+        xml.push_str(&chunk[1..]);
+        if chunk.starts_with('l') {
+            break;
+        }
+    }
+    assert!(xml.contains(r#"<reg name="pc" bitsize="16" type="code_ptr"/>"#));
+    assert_eq!(client.send("D"), "OK");
+    server.join().unwrap();
+}
+
+#[test]
+fn test_registers_and_memory() {
+    let (mut client, server) = connect(&ROM);
+    // 16 V registers, I and PC as two bytes each, then SP, DT and ST
+    let regs = client.send("g");
+    assert_eq!(regs.len(), (16 + 2 + 2 + 3) * 2);
+    assert_eq!(&regs[32..40], "00000002");
+
+    assert_eq!(client.send("P3=7f"), "OK");
+    assert_eq!(client.send("p3"), "7f");
+    assert_eq!(client.send("p11"), "0002");
+    assert_eq!(client.send("m200,4"), "6012a300");
+    assert_eq!(client.send("M400,2:beef"), "OK");
+    assert_eq!(client.send("m400,2"), "beef");
+    assert_eq!(client.send("m10000,1"), "E01");
+    assert_eq!(client.send("mffffffffffffffff,2"), "E01");
+    assert_eq!(client.send("Mffffffffffffffff,2:beef"), "E01");
+    assert_eq!(client.send("Mfff,2:beef"), "E01");
+    assert_eq!(client.send("m400,2"), "beef");
+
+    assert_eq!(client.send("D"), "OK");
+    let dbg = server.join().unwrap();
+    assert_eq!(dbg.emu().get_v_reg()[3], 0x7F);
+}
+
+#[test]
+fn test_step_breakpoint_and_watchpoint() {
+    let (mut client, server) = connect(&ROM);
+    assert_eq!(client.send("s"), "S05");
+    assert_eq!(client.send("p0"), "12");
+
+    assert_eq!(client.send("Z0,204,2"), "OK");
+    assert_eq!(client.send("c"), "S05");
+    assert_eq!(client.send("p11"), "0402");
+    assert_eq!(client.send("z0,204,2"), "OK");
+
+    assert_eq!(client.send("Z2,300,1"), "OK");
+    assert_eq!(client.send("c"), "T05watch:300;");
+    assert_eq!(client.send("m300,1"), "12");
+
+    // the ROM now spins forever, so continue only returns on Ctrl-C
+    write!(client.stream, "$c#63").unwrap();
+    assert_eq!(client.byte(), b'+');
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.reply(), "S02");
+
+    // kill gets no reply, the stub just closes the session
+    write!(client.stream, "$k#6b").unwrap();
+    server.join().unwrap();
+}
@@ -1,6 +0,0 @@
-extern crate chip8_core;
-
-#[test]
-fn test() {
-    assert_eq!(0, 0);
-}
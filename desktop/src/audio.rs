@@ -0,0 +1,49 @@
+use chip8_core::{AudioGenerator, AudioState};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::Sdl;
+
+const SAMPLE_RATE: i32 = 44100;
+
+pub struct Beeper {
+    generator: AudioGenerator,
+    channels: usize,
+    pub state: AudioState,
+}
+
+impl AudioCallback for Beeper {
+    type Channel = f32;
+
+    // SDL may hand back more channels than asked for, with their samples
+    // interleaved, so the mono tone goes out on every one of them
+    fn callback(&mut self, out: &mut [f32]) {
+        let frames = out.len() / self.channels;
+        self.generator.fill(&self.state, &mut out[..frames]);
+        // spread out from the back so no sample is overwritten before it's copied
+        for frame in (0..frames).rev() {
+            let sample = out[frame];
+            out[frame * self.channels..][..self.channels].fill(sample);
+        }
+    }
+}
+
+// Games still run without sound if there is no audio device to open
+pub fn open_beeper(sdl_context: &Sdl) -> Option<AudioDevice<Beeper>> {
+    let desired = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1),
+        samples: None,
+    };
+    let device = sdl_context
+        .audio()
+        .and_then(|audio| {
+            audio.open_playback(None, &desired, |spec| Beeper {
+                generator: AudioGenerator::new(spec.freq as u32),
+                channels: spec.channels.max(1) as usize,
+                state: AudioState::default(),
+            })
+        })
+        .map_err(|err| eprintln!("audio disabled: {}", err))
+        .ok()?;
+    device.resume();
+    Some(device)
+}
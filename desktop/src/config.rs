@@ -0,0 +1,135 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The config file is a list of `name = value` lines. Lines before the first
+// section apply to every ROM; a `[file name]` section holds overrides for the
+// ROM with that file name and applies on top of them:
+//
+//   # arrows for games that use 2/4/6/8
+//   key.2 = 2, Up
+//   key.8 = 8, Down
+//
+//   [BRIX.ch8]
+//   key.4 = Q, Left
+//
+// Lines starting with `#` are comments; a `#` anywhere else is part of the
+// value, as in `color.0 = #000000`. Each part of the frontend reads the
+// names it owns.
+
+/// One `name = value` line, with its line number for error messages.
+pub struct Entry {
+    pub line: usize,
+    pub name: String,
+    pub value: String,
+}
+
+struct Section {
+    rom: Option<String>,
+    entries: Vec<Entry>,
+}
+
+#[derive(Default)]
+pub struct Config {
+    path: String,
+    sections: Vec<Section>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/chip8/desktop.cfg`, or under `~/.config` without it.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(dir.join("chip8").join("desktop.cfg"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Self::parse(&path.display().to_string(), &text)
+    }
+
+    pub fn parse(path: &str, text: &str) -> Result<Self, String> {
+        let mut sections = vec![Section {
+            rom: None,
+            entries: Vec::new(),
+        }];
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(rom) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                sections.push(Section {
+                    rom: Some(rom.trim().to_string()),
+                    entries: Vec::new(),
+                });
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{}:{}: expected 'name = value'", path, idx + 1))?;
+            sections.last_mut().unwrap().entries.push(Entry {
+                line: idx + 1,
+                name: name.trim().to_string(),
+                value: value.trim().to_string(),
+            });
+        }
+        Ok(Self {
+            path: path.to_string(),
+            sections,
+        })
+    }
+
+    /// The entries that apply to the ROM at `rom_path`, the shared ones first
+    /// so that later, ROM-specific ones win.
+    pub fn entries<'a>(&'a self, rom_path: &str) -> impl Iterator<Item = &'a Entry> + 'a {
+        let file_name = Path::new(rom_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        self.sections
+            .iter()
+            .filter(move |section| section.rom.is_none() || section.rom == file_name)
+            .flat_map(|section| &section.entries)
+    }
+
+    /// An error message pointing at `entry`.
+    pub fn error(&self, entry: &Entry, message: &str) -> String {
+        format!("{}:{}: {}", self.path, entry.line, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+# shared by every ROM
+key.5 = W, Up
+palette = amber
+
+[BRIX.ch8]
+  # only for BRIX
+color.0 = #000000
+";
+
+    #[test]
+    fn test_sections_and_comments() {
+        let config = Config::parse("test.cfg", CONFIG).unwrap();
+        let names = |rom| {
+            config
+                .entries(rom)
+                .map(|entry| format!("{}={}", entry.name, entry.value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("roms/pong.ch8"), ["key.5=W, Up", "palette=amber"]);
+        assert_eq!(
+            names("roms/BRIX.ch8"),
+            ["key.5=W, Up", "palette=amber", "color.0=#000000"]
+        );
+        let entry = config.entries("BRIX.ch8").last().unwrap();
+        assert_eq!(config.error(entry, "oops"), "test.cfg:7: oops");
+        assert!(Config::parse("test.cfg", "key.5 W").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use crate::keymap::{Input, PadControl};
+use sdl2::controller::GameController;
+use sdl2::event::Event;
+use sdl2::{GameControllerSubsystem, Sdl};
+
+// how far a stick has to move before it counts as a direction; about a
+// third of the way, so a resting stick that drifts a little stays centred
+const DEAD_ZONE: i16 = 10_000;
+
+/// The connected game controllers. SDL reports the ones already plugged in
+/// as added at startup, so they and later ones go through `added`.
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    open: Vec<GameController>,
+}
+
+// Games still run on the keyboard if the controller subsystem won't start
+pub fn open_gamepads(sdl_context: &Sdl) -> Option<Gamepads> {
+    match sdl_context.game_controller() {
+        Ok(subsystem) => Some(Gamepads {
+            subsystem,
+            open: Vec::new(),
+        }),
+        Err(err) => {
+            eprintln!("no controller support: {}", err);
+            None
+        }
+    }
+}
+
+impl Gamepads {
+    /// Opens the controller at joystick `index` from a `ControllerDeviceAdded`.
+    pub fn added(&mut self, index: u32) {
+        match self.subsystem.open(index) {
+            Ok(controller) => {
+                println!("controller connected: {}", controller.name());
+                self.open.push(controller);
+            }
+            Err(err) => eprintln!("could not open controller: {}", err),
+        }
+    }
+
+    /// Closes the controller with instance `id` from a `ControllerDeviceRemoved`.
+    pub fn removed(&mut self, id: u32) {
+        if let Some(idx) = self.open.iter().position(|c| c.instance_id() == id) {
+            let controller = self.open.remove(idx);
+            println!("controller disconnected: {}", controller.name());
+        }
+    }
+}
+
+/// The inputs a controller button or stick event presses and releases. A
+/// stick movement can release one direction of an axis and press the other.
+pub fn pad_inputs(event: &Event) -> Vec<(Input, bool)> {
+    match *event {
+        Event::ControllerButtonDown { which, button, .. } => {
+            vec![(Input::Pad(which, PadControl::Button(button)), true)]
+        }
+        Event::ControllerButtonUp { which, button, .. } => {
+            vec![(Input::Pad(which, PadControl::Button(button)), false)]
+        }
+        Event::ControllerAxisMotion {
+            which, axis, value, ..
+        } => vec![
+            (
+                Input::Pad(which, PadControl::Axis(axis, true)),
+                value > DEAD_ZONE,
+            ),
+            (
+                Input::Pad(which, PadControl::Axis(axis, false)),
+                value < -DEAD_ZONE,
+            ),
+        ],
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,189 @@
+use crate::config::Config;
+use sdl2::controller::{Axis, Button};
+use sdl2::keyboard::Scancode;
+use std::collections::HashSet;
+
+const NUM_KEYS: usize = 16;
+
+// Scancodes name physical positions, so the keypad sits on the same 4x4 block
+// whatever the keyboard layout: 1234/QWER/ASDF/ZXCV on QWERTY.
+const DEFAULT_LAYOUT: [(Scancode, usize); NUM_KEYS] = [
+    (Scancode::Num1, 0x1),
+    (Scancode::Num2, 0x2),
+    (Scancode::Num3, 0x3),
+    (Scancode::Num4, 0xC),
+    (Scancode::Q, 0x4),
+    (Scancode::W, 0x5),
+    (Scancode::E, 0x6),
+    (Scancode::R, 0xD),
+    (Scancode::A, 0x7),
+    (Scancode::S, 0x8),
+    (Scancode::D, 0x9),
+    (Scancode::F, 0xE),
+    (Scancode::Z, 0xA),
+    (Scancode::X, 0x0),
+    (Scancode::C, 0xB),
+    (Scancode::V, 0xF),
+];
+
+// D-pad and left stick on 5/7/8/9, the WASD block most ROMs steer with, and
+// the face buttons on nearby keys games tend to use for actions.
+const DEFAULT_PAD_LAYOUT: [(PadControl, usize); 12] = [
+    (PadControl::Button(Button::DPadUp), 0x5),
+    (PadControl::Button(Button::DPadLeft), 0x7),
+    (PadControl::Button(Button::DPadDown), 0x8),
+    (PadControl::Button(Button::DPadRight), 0x9),
+    (PadControl::Axis(Axis::LeftY, false), 0x5),
+    (PadControl::Axis(Axis::LeftX, false), 0x7),
+    (PadControl::Axis(Axis::LeftY, true), 0x8),
+    (PadControl::Axis(Axis::LeftX, true), 0x9),
+    (PadControl::Button(Button::A), 0x6),
+    (PadControl::Button(Button::B), 0x4),
+    (PadControl::Button(Button::X), 0xA),
+    (PadControl::Button(Button::Y), 0xF),
+];
+
+/// A button or stick direction on a game controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PadControl {
+    Button(Button),
+    /// An axis pushed past the dead zone, towards its positive end if true.
+    Axis(Axis, bool),
+}
+
+impl PadControl {
+    /// Parses an SDL mapping name such as `a` or `dpup`, or an axis name
+    /// with a direction such as `lefty-`.
+    fn from_name(name: &str) -> Option<Self> {
+        if let Some(axis) = name.strip_suffix('+') {
+            return Axis::from_string(axis).map(|axis| PadControl::Axis(axis, true));
+        }
+        if let Some(axis) = name.strip_suffix('-') {
+            return Axis::from_string(axis).map(|axis| PadControl::Axis(axis, false));
+        }
+        Button::from_string(name).map(PadControl::Button)
+    }
+}
+
+/// Something held down on the host: a keyboard key, or a control on the
+/// game controller with that joystick instance id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(Scancode),
+    Pad(u32, PadControl),
+}
+
+/// Which host keys and controller inputs press which CHIP-8 key. Several
+/// inputs can share one CHIP-8 key, which stays down until all of them are
+/// released; every connected controller uses the same bindings.
+pub struct Keymap {
+    keys: [Vec<Scancode>; NUM_KEYS],
+    pad: [Vec<PadControl>; NUM_KEYS],
+    held: HashSet<Input>,
+}
+
+impl Keymap {
+    /// The default layout with the config's entries for `rom_path` applied:
+    /// `key.N = Name, Name` with SDL scancode names such as `W`, `Up` or
+    /// `Keypad 8`, and `pad.N = name, name` with SDL controller names such
+    /// as `a`, `dpup` or `leftx+`. An empty list unbinds the key.
+    pub fn load(config: &Config, rom_path: &str) -> Result<Self, String> {
+        let mut keymap = Self::default();
+        for entry in config.entries(rom_path) {
+            let (kind, key) = match entry.name.split_once('.') {
+                Some((kind @ ("key" | "pad"), key)) => (kind, key),
+                _ => continue,
+            };
+            let key = usize::from_str_radix(key, 16)
+                .ok()
+                .filter(|key| *key < NUM_KEYS)
+                .ok_or_else(|| config.error(entry, &format!("expected {0}.0 to {0}.f", kind)))?;
+            let names = entry
+                .value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty());
+            let unknown = |name| config.error(entry, &format!("unknown {} '{}'", kind, name));
+            if kind == "key" {
+                let scancodes = names
+                    .map(|name| Scancode::from_name(name).ok_or_else(|| unknown(name)))
+                    .collect::<Result<_, _>>()?;
+                bind(&mut keymap.keys, key, scancodes);
+            } else {
+                let controls = names
+                    .map(|name| PadControl::from_name(name).ok_or_else(|| unknown(name)))
+                    .collect::<Result<_, _>>()?;
+                bind(&mut keymap.pad, key, controls);
+            }
+        }
+        Ok(keymap)
+    }
+
+    pub fn lookup(&self, input: Input) -> Option<usize> {
+        match input {
+            Input::Key(scancode) => self.keys.iter().position(|keys| keys.contains(&scancode)),
+            Input::Pad(_, control) => self.pad.iter().position(|pad| pad.contains(&control)),
+        }
+    }
+
+    /// Notes an input going down or up and returns the CHIP-8 key and its
+    /// new state if that changed. Key repeats and a second input for an
+    /// already held CHIP-8 key return `None`.
+    pub fn update(&mut self, input: Input, pressed: bool) -> Option<(usize, bool)> {
+        let key = self.lookup(input)?;
+        let was_held = self.is_held(key);
+        if pressed {
+            self.held.insert(input);
+        } else {
+            self.held.remove(&input);
+        }
+        let held = self.is_held(key);
+        (held != was_held).then_some((key, held))
+    }
+
+    /// Lets go of everything held on a controller that was unplugged.
+    pub fn release_pad(&mut self, id: u32) -> Vec<(usize, bool)> {
+        let held: Vec<Input> = self
+            .held
+            .iter()
+            .copied()
+            .filter(|input| matches!(input, Input::Pad(pad, _) if *pad == id))
+            .collect();
+        held.into_iter()
+            .filter_map(|input| self.update(input, false))
+            .collect()
+    }
+
+    fn is_held(&self, key: usize) -> bool {
+        self.held
+            .iter()
+            .any(|input| self.lookup(*input) == Some(key))
+    }
+}
+
+// Binds `hosts` to `key` in place of its current ones. An input presses a
+// single CHIP-8 key, so they are unbound from any other.
+fn bind<T: PartialEq>(bindings: &mut [Vec<T>; NUM_KEYS], key: usize, hosts: Vec<T>) {
+    for bound in bindings.iter_mut() {
+        bound.retain(|host| !hosts.contains(host));
+    }
+    bindings[key] = hosts;
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keys: [Vec<Scancode>; NUM_KEYS] = Default::default();
+        for (scancode, key) in DEFAULT_LAYOUT {
+            keys[key].push(scancode);
+        }
+        let mut pad: [Vec<PadControl>; NUM_KEYS] = Default::default();
+        for (control, key) in DEFAULT_PAD_LAYOUT {
+            pad[key].push(control);
+        }
+        Self {
+            keys,
+            pad,
+            held: HashSet::new(),
+        }
+    }
+}
@@ -1,25 +1,65 @@
+mod audio;
+mod config;
+mod gamepad;
+mod keymap;
+mod palette;
+
 use chip8_core::*;
+use config::Config;
+use keymap::{Input, Keymap};
+use palette::Palette;
 use std::env;
-use std::fs::File;
-use std::io::Read;
-use sdl2::event::Event;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
-const SCALE: u32 = 15;
+const SCALE: u32 = 16;
 const WINDOW_WIDTH: u32 = (SCREEN_WIDTH as u32) * SCALE;
 const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT as u32) * SCALE;
-const TICKS_PER_FRAME: usize = 20;
+const DEFAULT_REWIND_KB: usize = 4096;
+// the scheduler keeps time, this only stops the loop spinning while the screen is unchanged
+const FRAME_TIME: Duration = Duration::from_micros(16_667);
+const USAGE: &str = "usage: cargo run [--platform chip8|schip|xo] [--quirks vip|schip|xo|modern] \
+                     [--ips n] [--rewind-kb size] [--seed n] [--config file] \
+                     [--palette classic|amber|green|lcd|octo] \
+                     [--record out.movie | --play in.movie] [path/to/file]";
 
 fn main() {
-    // Reads input to file 
     let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        println!("usage: cargo run [path/to/file]");
-        return;
-    }
+    let opts = match parse_args(&args[1..]) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+    let config = match load_config(opts.config.as_deref()) {
+        Ok(config) => config,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+    let mut keymap = match Keymap::load(&config, &opts.path) {
+        Ok(keymap) => keymap,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+    let mut palette = match Palette::load(&config, &opts.path, opts.palette) {
+        Ok(palette) => palette,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -35,15 +75,59 @@ fn main() {
     canvas.present();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut beeper = audio::open_beeper(&sdl_context);
+    let mut gamepads = gamepad::open_gamepads(&sdl_context);
 
-    let mut chip8 = Emu::new();
-
-    let mut rom = File::open(&args[1]).expect("Unable to open file");
-    let mut buffer = Vec::new();
+    let mut chip8 = Emu::with_platform(opts.platform, opts.quirks);
+    if let Some(seed) = opts.seed {
+        chip8.reseed(seed);
+    }
 
-    rom.read_to_end(&mut buffer).unwrap();
-    chip8.load(&buffer);
+    let mut buffer = match fs::read(&opts.path) {
+        Ok(buffer) => buffer,
+        Err(err) => {
+            eprintln!("{}: {}", opts.path, err);
+            process::exit(1);
+        }
+    };
+    if opts.path.ends_with(".8o") {
+        // Octo source is compiled for the selected platform on the fly
+        let source = String::from_utf8_lossy(&buffer);
+        match octo::compile(&source, opts.platform) {
+            Ok(program) => buffer = program.rom,
+            Err(err) => {
+                println!("{}:{}", opts.path, err);
+                return;
+            }
+        }
+    }
+    if let Err(err) = chip8.load(&buffer) {
+        println!("{}: {}", opts.path, err);
+        return;
+    }
+    if let Some(movie) = &opts.movie {
+        chip8 = match movie.boot(&buffer) {
+            Ok(emu) => emu,
+            Err(err) => {
+                println!("{}: {}", opts.path, err);
+                return;
+            }
+        };
+    } else if opts.record.is_some() {
+        // parse_args always picks a seed when recording
+        let seed = opts.seed.unwrap();
+        chip8.start_recording(Movie::new(&buffer, opts.platform, opts.quirks, seed, opts.ips));
+    }
+    // rewinding or loading a state would desync the movie from its key events
+    let movie_active = opts.movie.is_some() || opts.record.is_some();
 
+    let state_path = format!("{}.state", opts.path);
+    let mut history = RewindBuffer::new(opts.rewind_kb * 1024);
+    let mut rewinding = false;
+    let mut halted = false;
+    let mut redraw = true;
+    let mut scheduler = Scheduler::new(opts.ips);
+    let mut last_frame = Instant::now();
     'gameloop: loop {
         for evt in event_pump.poll_iter() {
             match evt {
@@ -54,68 +138,287 @@ fn main() {
                 } => {
                     break 'gameloop;
                 }
+                Event::Window {
+                    win_event: WindowEvent::Exposed,
+                    ..
+                } => redraw = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } => {
+                    palette = palette.next();
+                    println!("palette: {}", palette.name);
+                    redraw = true;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => match fs::write(&state_path, chip8.save_state()) {
+                    Ok(()) => println!("saved state to {}", state_path),
+                    Err(err) => eprintln!("could not save state: {}", err),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } if movie_active => eprintln!("can't load a state while a movie is running"),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => match quick_load(&mut chip8, &state_path) {
+                    Ok(()) => {
+                        println!("loaded state from {}", state_path);
+                        canvas.window_mut().set_title("Chip8 Emu").unwrap();
+                        history.clear();
+                        halted = false;
+                    }
+                    Err(err) => eprintln!("could not load state: {}", err),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = !movie_active,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(pads) = gamepads.as_mut() {
+                        pads.added(which);
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(pads) = gamepads.as_mut() {
+                        pads.removed(which);
+                    }
+                    for (k, pressed) in keymap.release_pad(which) {
+                        chip8.keypress(k, pressed).unwrap();
+                    }
+                }
+                // the movie does the playing until it runs out
+                Event::KeyDown { .. }
+                | Event::KeyUp { .. }
+                | Event::ControllerButtonDown { .. }
+                | Event::ControllerButtonUp { .. }
+                | Event::ControllerAxisMotion { .. }
+                    if chip8.is_playing() => {}
                 Event::KeyDown {
-                    keycode: Some(key), ..
+                    scancode: Some(scancode),
+                    ..
                 } => {
-                    if let Some(k) = key2btn(key) {
-                        chip8.keypress(k, true);
+                    if let Some((k, pressed)) = keymap.update(Input::Key(scancode), true) {
+                        chip8.keypress(k, pressed).unwrap();
                     }
                 }
                 Event::KeyUp {
-                    keycode: Some(key), ..
+                    scancode: Some(scancode),
+                    ..
                 } => {
-                    if let Some(k) = key2btn(key) {
-                        chip8.keypress(k, false);
+                    if let Some((k, pressed)) = keymap.update(Input::Key(scancode), false) {
+                        chip8.keypress(k, pressed).unwrap();
+                    }
+                }
+                Event::ControllerButtonDown { .. }
+                | Event::ControllerButtonUp { .. }
+                | Event::ControllerAxisMotion { .. } => {
+                    for (input, pressed) in gamepad::pad_inputs(&evt) {
+                        if let Some((k, pressed)) = keymap.update(input, pressed) {
+                            chip8.keypress(k, pressed).unwrap();
+                        }
                     }
                 }
                 _ => (),
             }
         }
 
-        for _ in 0..TICKS_PER_FRAME {
-            chip8.tick();
+        let now = Instant::now();
+        let elapsed = now - last_frame;
+        last_frame = now;
+        if rewinding {
+            // stepping back also recovers from a fault
+            if history.rewind(&mut chip8) && halted {
+                canvas.window_mut().set_title("Chip8 Emu").unwrap();
+                halted = false;
+            }
+        } else if !halted {
+            match scheduler.advance(&mut chip8, elapsed) {
+                Ok(0) => {}
+                Ok(_) => history.push(&chip8),
+                Err(err) => {
+                    eprintln!("emulation halted: {}", err);
+                    canvas
+                        .window_mut()
+                        .set_title(&format!("Chip8 Emu - {}", err))
+                        .unwrap();
+                    halted = true;
+                }
+            }
+        }
+        if let Some(device) = beeper.as_mut() {
+            device.lock().state = if halted {
+                AudioState::default()
+            } else {
+                chip8.audio_state()
+            };
+        }
+        if chip8.has_exited() {
+            break 'gameloop;
+        }
+        if !chip8.take_dirty().is_empty() || redraw {
+            draw_screen(&chip8, &palette, &mut canvas);
+            redraw = false;
+        } else {
+            thread::sleep(FRAME_TIME);
+        }
+    }
+    if let (Some(movie), Some(path)) = (chip8.stop_recording(), &opts.record) {
+        match fs::write(path, movie.to_text()) {
+            Ok(()) => println!("saved movie to {}", path),
+            Err(err) => eprintln!("could not save movie: {}", err),
         }
-        chip8.tick_timers();
-        draw_screen(&chip8, &mut canvas);
     }
 }
 
-fn draw_screen(emu: &Emu, canvas: &mut Canvas<Window>) {
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+struct Options {
+    path: String,
+    platform: Platform,
+    quirks: Quirks,
+    ips: u32,
+    rewind_kb: usize,
+    seed: Option<u64>,
+    config: Option<String>,
+    palette: Option<Palette>,
+    record: Option<String>,
+    movie: Option<Movie>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut platform = Platform::default();
+    let mut quirks = None;
+    let mut ips = Scheduler::DEFAULT_IPS;
+    let mut rewind_kb = DEFAULT_REWIND_KB;
+    let mut seed = None;
+    let mut config = None;
+    let mut palette = None;
+    let mut record = None;
+    let mut movie = None;
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                platform = args
+                    .next()
+                    .and_then(|name| Platform::from_name(name))
+                    .ok_or("unknown platform, expected chip8, schip or xo")?;
+            }
+            "--quirks" => {
+                quirks = Some(
+                    args.next()
+                        .and_then(|name| Quirks::preset(name))
+                        .ok_or("unknown quirks profile, expected vip, schip, xo or modern")?,
+                );
+            }
+            "--ips" => {
+                ips = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| *n > 0)
+                    .ok_or("--ips expects a number of instructions per second")?;
+            }
+            "--rewind-kb" => {
+                rewind_kb = args
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .ok_or("--rewind-kb expects a size in KiB")?;
+            }
+            "--seed" => {
+                seed = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or("--seed expects an unsigned integer")?,
+                );
+            }
+            "--config" => config = Some(args.next().ok_or("--config expects a path")?.clone()),
+            "--palette" => {
+                palette = Some(
+                    args.next()
+                        .and_then(|name| Palette::from_name(name))
+                        .ok_or("unknown palette, expected classic, amber, green, lcd or octo")?,
+                );
+            }
+            "--record" => record = Some(args.next().ok_or("--record expects a path")?.clone()),
+            "--play" => {
+                let file = args.next().ok_or("--play expects a path")?;
+                let text = fs::read_to_string(file).map_err(|err| format!("{}: {}", file, err))?;
+                movie = Some(Movie::parse(&text).map_err(|err| format!("{}:{}", file, err))?);
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+    let mut quirks = quirks.unwrap_or_else(|| platform.default_quirks());
+    if let Some(movie) = &movie {
+        if record.is_some() {
+            return Err("--play can't be combined with --record".to_string());
+        }
+        platform = movie.platform;
+        quirks = movie.quirks;
+        seed = Some(movie.seed);
+        ips = movie.ips;
+    }
+    if record.is_some() && seed.is_none() {
+        // a movie has to name its seed, so pick it here rather than inside the emulator
+        seed = Some(random_seed());
+    }
+    Ok(Options {
+        path,
+        platform,
+        quirks,
+        ips,
+        rewind_kb,
+        seed,
+        config,
+        palette,
+        record,
+        movie,
+    })
+}
+
+// an explicit --config has to exist, the default one is optional
+fn load_config(path: Option<&str>) -> Result<Config, String> {
+    match path {
+        Some(path) => Config::load(Path::new(path)),
+        None => match Config::default_path().filter(|path| path.exists()) {
+            Some(path) => Config::load(&path),
+            None => Ok(Config::default()),
+        },
+    }
+}
+
+fn quick_load(emu: &mut Emu, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let data = fs::read(path)?;
+    emu.load_state(&data)?;
+    Ok(())
+}
+
+fn draw_screen(emu: &Emu, palette: &Palette, canvas: &mut Canvas<Window>) {
+    canvas.set_draw_color(palette.background());
     canvas.clear();
 
     let screen_buf = emu.get_display();
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    let second_plane = emu.get_plane(1);
+    let (width, _) = emu.get_display_size();
+    let scale = WINDOW_WIDTH / width as u32;
     for (i, pixel) in screen_buf.iter().enumerate() {
-        if *pixel {
-            let x = (i % SCREEN_WIDTH) as u32;
-            let y = (i / SCREEN_WIDTH) as u32;
-            let rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
+        if let Some(color) = palette.pixel(*pixel, second_plane[i]) {
+            let x = (i % width) as u32;
+            let y = (i / width) as u32;
+            let rect = Rect::new((x * scale) as i32, (y * scale) as i32, scale, scale);
+            canvas.set_draw_color(color);
             canvas.fill_rect(rect).unwrap();
         }
     }
     canvas.present();
 }
 
-
-fn key2btn(key: Keycode) -> Option<usize> {
-    match key {
-        Keycode::Num1 => Some(0x1),
-        Keycode::Num2 => Some(0x2),
-        Keycode::Num3 => Some(0x3),
-        Keycode::Num4 => Some(0xC),
-        Keycode::Q => Some(0x4),
-        Keycode::W => Some(0x5),
-        Keycode::E => Some(0x6),
-        Keycode::R => Some(0xD),
-        Keycode::A => Some(0x7),
-        Keycode::S => Some(0x8),
-        Keycode::D => Some(0x9),
-        Keycode::F => Some(0xE),
-        Keycode::Z => Some(0xA),
-        Keycode::X => Some(0x0),
-        Keycode::C => Some(0xB),
-        Keycode::V => Some(0xF),
-        _ => None,
-    }
-}
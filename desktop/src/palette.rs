@@ -0,0 +1,136 @@
+use crate::config::Config;
+use sdl2::pixels::Color;
+
+/// Display colours, indexed by the XO-CHIP planes a pixel is lit in: the
+/// background, plane 0 (the only one CHIP-8 and SUPER-CHIP draw on), plane 1,
+/// and both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub name: &'static str,
+    pub colors: [Color; 4],
+}
+
+pub const THEMES: [Palette; 5] = [
+    theme("classic", [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+    theme("amber", [0x140C00, 0xFFB000, 0xA87400, 0xFFD880]),
+    theme("green", [0x001400, 0x33FF33, 0x1F991F, 0xA0FFA0]),
+    theme("lcd", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),
+    // Octo's defaults, which XO-CHIP games are usually made to look right in
+    theme("octo", [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+];
+
+const fn theme(name: &'static str, hex: [u32; 4]) -> Palette {
+    Palette {
+        name,
+        colors: [rgb(hex[0]), rgb(hex[1]), rgb(hex[2]), rgb(hex[3])],
+    }
+}
+
+const fn rgb(hex: u32) -> Color {
+    Color::RGB((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+}
+
+impl Palette {
+    pub fn from_name(name: &str) -> Option<Self> {
+        THEMES.iter().find(|theme| theme.name == name).copied()
+    }
+
+    /// The palette the config picks for `rom_path`: the theme named by
+    /// `palette`, or `theme` if given, with any `color.N = #RRGGBB` entries
+    /// replacing single colours.
+    pub fn load(config: &Config, rom_path: &str, theme: Option<Self>) -> Result<Self, String> {
+        let mut palette = THEMES[0];
+        let mut colors = Vec::new();
+        for entry in config.entries(rom_path) {
+            if entry.name == "palette" {
+                palette = Self::from_name(&entry.value)
+                    .ok_or_else(|| config.error(entry, "unknown palette"))?;
+            } else if let Some(idx) = entry.name.strip_prefix("color.") {
+                let idx: usize = idx
+                    .parse()
+                    .ok()
+                    .filter(|idx| *idx < palette.colors.len())
+                    .ok_or_else(|| config.error(entry, "expected color.0 to color.3"))?;
+                let color = entry
+                    .value
+                    .strip_prefix('#')
+                    .filter(|hex| hex.len() == 6)
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| config.error(entry, "expected a colour like #FFB000"))?;
+                colors.push((idx, rgb(color)));
+            }
+        }
+        // a theme picked on the command line wins over the config's
+        if let Some(theme) = theme {
+            palette = theme;
+        }
+        for (idx, color) in colors {
+            palette.colors[idx] = color;
+        }
+        Ok(palette)
+    }
+
+    /// The built-in theme after this one, for cycling with a hotkey.
+    pub fn next(&self) -> Self {
+        let idx = THEMES.iter().position(|theme| theme.name == self.name);
+        THEMES[idx.map_or(0, |idx| (idx + 1) % THEMES.len())]
+    }
+
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    /// The colour of a pixel lit in the given planes, `None` if it is in neither.
+    pub fn pixel(&self, plane0: bool, plane1: bool) -> Option<Color> {
+        match (plane0, plane1) {
+            (false, false) => None,
+            (true, false) => Some(self.colors[1]),
+            (false, true) => Some(self.colors[2]),
+            (true, true) => Some(self.colors[3]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colour_overrides() {
+        let text =
+            "palette = amber\ncolor.0 = #000000\n[lcd.ch8]\npalette = lcd\ncolor.3 = #FF0080\n";
+        let config = Config::parse("test.cfg", text).unwrap();
+
+        let amber = Palette::from_name("amber").unwrap();
+        let palette = Palette::load(&config, "pong.ch8", None).unwrap();
+        assert_eq!(palette.name, "amber");
+        assert_eq!(palette.background(), Color::RGB(0, 0, 0));
+        assert_eq!(palette.colors[1..], amber.colors[1..]);
+
+        // overrides from every section apply to the theme picked last
+        let palette = Palette::load(&config, "lcd.ch8", None).unwrap();
+        assert_eq!(palette.name, "lcd");
+        assert_eq!(palette.background(), Color::RGB(0, 0, 0));
+        assert_eq!(
+            palette.pixel(true, true),
+            Some(Color::RGB(0xFF, 0x00, 0x80))
+        );
+
+        let octo = Palette::from_name("octo");
+        let palette = Palette::load(&config, "pong.ch8", octo).unwrap();
+        assert_eq!(palette.name, "octo");
+        assert_eq!(palette.pixel(true, false), Some(octo.unwrap().colors[1]));
+    }
+
+    #[test]
+    fn test_bad_colours() {
+        for line in ["color.0 = 000000", "color.0 = #FFF", "color.4 = #000000"] {
+            let config = Config::parse("test.cfg", line).unwrap();
+            assert!(
+                Palette::load(&config, "pong.ch8", None).is_err(),
+                "{}",
+                line
+            );
+        }
+    }
+}